@@ -1,309 +1,1850 @@
 use crate::types::*;
 use crate::errors::*;
+use crate::store::*;
+use crate::timespec;
+use crate::export::{self, ImportMode};
+#[cfg(unix)]
+use crate::daemon;
 
-use std::cmp;
+use std::collections::{BTreeMap, HashMap};
 use std::process::{Command, Stdio};
 use std::thread;
 use std::time::Duration;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::env;
 
-use regex::Regex;
-use rusqlite::Transaction;
-use rusqlite::{Connection, params, named_params, OptionalExtension};
-use rusqlite::Result as RusqliteResult;
+use chrono::{Local, TimeZone, Utc};
 use notify_rust::Notification;
 use uuid::Uuid;
 
+/// Neighbouring tasks whose orders are closer than this get their (sub-)stack
+/// renormalized before anything goes between them, while the gap is still exact.
+const MIN_ORDER_GAP: f64 = 1e-6;
+
+/// Name under which time spent on deleted tasks is added up.
+const DELETED_TASKS: &str = "(deleted tasks)";
+
+/// Get the current time.
+pub(crate) fn now() -> Timestamp {
+    Utc::now().timestamp()
+}
+
+/// Turn stored tasks into [`Task`]s, numbering them from 0 if `indexed`.
+fn tasks_from_records<S: Store>(store: &S, records: Vec<TaskRecord>, indexed: bool) -> AppResult<Vec<Task>> {
+    let stack_names: HashMap<StackId, String> = store.stacks()?.into_iter().map(|stack| (stack.id, stack.name)).collect();
+    let mut reminder_counts: HashMap<TaskId, u32> = HashMap::new();
+    for reminder in store.reminders()?.into_iter().filter(|reminder| !reminder.cancelled) {
+        *reminder_counts.entry(reminder.task_id).or_default() += 1;
+    }
+    let mut tags: HashMap<TaskId, Vec<String>> = HashMap::new();
+    for tag in store.tags()? {
+        tags.entry(tag.task_id).or_default().push(tag.tag);
+    }
+    let tasks = records.into_iter().enumerate().map(|(i, record)| Task {
+        index: indexed.then_some(i as TaskIndex),
+        id: record.id,
+        short_id: TaskRef::Id(record.id).to_string(),
+        stack: stack_names.get(&record.stack_id).cloned().expect("bug: task on a stack that doesn't exist"),
+        status: record.status,
+        created_at: record.created_at,
+        finished_at: record.finished_at,
+        reminders: reminder_counts.get(&record.id).copied().unwrap_or_default(),
+        tags: tags.remove(&record.id).unwrap_or_default(),
+        task: record.task,
+    }).collect();
+    Ok(tasks)
+}
+
+/// Get the stored task with ID `task_id`, which must exist.
+fn get_task_record<S: Store>(store: &S, task_id: TaskId) -> AppResult<TaskRecord> {
+    Ok(store.task(task_id)?.expect("bug: task disappeared"))
+}
+
+/// Get the task with ID `task_id`, which must exist.
+fn get_task<S: Store>(store: &S, task_id: TaskId) -> AppResult<Task> {
+    let record = get_task_record(store, task_id)?;
+    Ok(tasks_from_records(store, vec![record], false)?.remove(0))
+}
+
 /// Get the ID of the current stack.
-pub fn get_current_stack_id(db: &Connection) -> AppResult<StackId> {
-    let stack_id: StackId = db.query_row("SELECT stack_id FROM app_state", [], |row| row.get(0))?;
-    Ok(stack_id)
+pub fn get_current_stack_id<S: Store>(store: &S) -> AppResult<StackId> {
+    Ok(store.context()?.stack_id)
 }
 
 /// Get the name of the current stack.
-pub fn get_current_stack_name(db: &Connection) -> AppResult<String> {
-    let current_stack_id = get_current_stack_id(db)?;
-    let current_stack_name: String = db.query_row("SELECT name FROM stacks WHERE id = ?", params![current_stack_id], |row| row.get(0))?;
-    Ok(current_stack_name)
+pub fn get_current_stack_name<S: Store>(store: &S) -> AppResult<String> {
+    let current_stack_id = get_current_stack_id(store)?;
+    let stack = store.stacks()?.into_iter().find(|stack| stack.id == current_stack_id).expect("bug: current stack doesn't exist");
+    Ok(stack.name)
+}
+
+/// Get the tasks commands currently operate on.
+pub fn get_current_context<S: Store>(store: &S) -> AppResult<Context> {
+    store.context()
+}
+
+/// Get the text of every task whose sub-stack encloses the current context, outermost first.
+pub fn get_current_context_path<S: Store>(store: &S) -> AppResult<Vec<String>> {
+    let context = get_current_context(store)?;
+    let mut path = Vec::new();
+    let mut parent_id = context.parent_id;
+    while let Some(id) = parent_id {
+        let parent = get_task_record(store, id)?;
+        path.push(parent.task);
+        parent_id = parent.parent_id;
+    }
+    path.reverse();
+    Ok(path)
+}
+
+/// Add an open task called `task` in `context` at `order`, returning it.
+///
+/// The task is tagged with any tags written in its text.
+fn insert_task<S: Store>(store: &mut S, task: String, order: f64, context: Context) -> AppResult<Task> {
+    let tags = inline_tags(&task);
+    let task_id = store.insert_task(&TaskRecord {
+        id: 0,
+        task,
+        order,
+        stack_id: context.stack_id,
+        parent_id: context.parent_id,
+        status: TaskStatus::Open,
+        created_at: Some(now()),
+        finished_at: None,
+    })?;
+    for tag in tags {
+        store.insert_tag(&TagRecord { task_id, tag })?;
+    }
+    get_task(store, task_id)
+}
+
+/// Add `tags` to the open task `task_ref`.
+pub fn tag_task<S: Store>(store: &mut S, task_ref: TaskRef, tags: &[String]) -> AppResult<Task> {
+    let (task_id, _) = resolve_task_ref(store, task_ref)?;
+    store.transaction(|store| {
+        for tag in tags {
+            store.insert_tag(&TagRecord { task_id, tag: parse_tag(tag)? })?;
+        }
+        Ok(())
+    })?;
+    get_task(store, task_id)
+}
+
+/// Replace the text of the stored task `task`, keeping its tags in step with the tags written in it.
+///
+/// Returns whether the text changed.
+fn set_task_text<S: Store>(store: &mut S, mut task: TaskRecord, text: String) -> AppResult<bool> {
+    if text.trim().is_empty() {
+        return Err(TaskError::EmptyTask.into());
+    }
+    if text == task.task {
+        return Ok(false);
+    }
+    let new_tags = inline_tags(&text);
+    for tag in inline_tags(&task.task).into_iter().filter(|tag| !new_tags.contains(tag)) {
+        store.delete_tag(&TagRecord { task_id: task.id, tag })?;
+    }
+    for tag in new_tags {
+        store.insert_tag(&TagRecord { task_id: task.id, tag })?;
+    }
+    task.task = text;
+    store.update_task(&task)?;
+    Ok(true)
+}
+
+/// Replace the text of the open task `task_ref`. Its reminders, notes and
+/// place on the stack are kept.
+pub fn edit_task<S: Store>(store: &mut S, task_ref: TaskRef, text: String) -> AppResult<Task> {
+    let (task_id, _) = resolve_task_ref(store, task_ref)?;
+    let task = get_task_record(store, task_id)?;
+    store.transaction(|store| set_task_text(store, task, text))?;
+    get_task(store, task_id)
+}
+
+/// Apply `substitution` to the text of every open task on the stack `stack_id`,
+/// sub-stacks included, returning the tasks that changed.
+pub fn substitute_tasks<S: Store>(store: &mut S, stack_id: StackId, substitution: &Substitution) -> AppResult<Vec<Task>> {
+    let tasks = store.open_tasks_on_stack(stack_id)?;
+    let changed = store.transaction(|store| {
+        let mut changed = Vec::new();
+        for task in tasks {
+            let (task_id, text) = (task.id, substitution.apply(&task.task));
+            if set_task_text(store, task, text)? {
+                changed.push(task_id);
+            }
+        }
+        Ok(changed)
+    })?;
+    changed.into_iter().map(|task_id| get_task(store, task_id)).collect()
+}
+
+/// Add a note to the open task `task_ref`.
+pub fn add_note<S: Store>(store: &mut S, task_ref: TaskRef, note: String) -> AppResult<Task> {
+    let (task_id, _) = resolve_task_ref(store, task_ref)?;
+    let note = note.trim().to_string();
+    if note.is_empty() {
+        return Err(TaskError::EmptyNote.into());
+    }
+    store.insert_note(&NoteRecord { id: 0, task_id, note, created_at: now() })?;
+    get_task(store, task_id)
+}
+
+/// Get a task with its notes and pending reminders.
+///
+/// Unlike most commands, this also finds finished tasks, by ID.
+pub fn show_task<S: Store>(store: &S, task_ref: TaskRef) -> AppResult<TaskDetails> {
+    let record = match task_ref {
+        TaskRef::Index(_) => get_task_record(store, resolve_task_ref(store, task_ref)?.0)?,
+        TaskRef::Id(id) => store.task(id)?.ok_or(TaskError::NoSuchTask(task_ref))?,
+    };
+    // Open tasks get their index in whichever (sub-)stack they're in.
+    let task = if record.status == TaskStatus::Open {
+        tasks_from_records(store, store.open_tasks(record.context())?, true)?.into_iter()
+            .find(|task| task.id == record.id)
+            .expect("bug: open task not in its context")
+    } else {
+        tasks_from_records(store, vec![record], false)?.remove(0)
+    };
+    let notes = store.notes(task.id)?.into_iter().map(|note| Note { note: note.note, created_at: note.created_at }).collect();
+    let reminders = list_reminders(store)?.into_iter().filter(|reminder| reminder.task_id == task.id).collect();
+    Ok(TaskDetails { task, notes, reminders })
 }
 
 /// Push `task` onto the top of the stack.
-pub fn push_task(db: &Connection, task: String) -> AppResult<()> {
-    let current_stack_id = get_current_stack_id(db)?;
-    db.execute("INSERT INTO tasks(task, task_order, stack_id) VALUES (?, (SELECT coalesce(max(task_order) + 1, 1) FROM tasks), ?)", params![task, current_stack_id])?;
-    Ok(())
+pub fn push_task<S: Store>(store: &mut S, task: String) -> AppResult<Task> {
+    let context = get_current_context(store)?;
+    let order = top_order(store, context)?;
+    insert_task(store, task, order, context)
 }
 
 /// Put `task` onto the bottom of the stack.
-pub fn pushback_task(db: &Connection, task: String) -> AppResult<()> {
-    let current_stack_id = get_current_stack_id(db)?;
-    db.execute("INSERT INTO tasks(task, task_order, stack_id) VALUES (?, (SELECT coalesce(min(task_order) - 1, 1) FROM tasks), ?)", params![task, current_stack_id])?;
-    Ok(())
+pub fn pushback_task<S: Store>(store: &mut S, task: String) -> AppResult<Task> {
+    let context = get_current_context(store)?;
+    let order = bottom_order(store, context)?;
+    insert_task(store, task, order, context)
 }
 
-/// Pop the top task off the stack.
-pub fn pop_task(db: &Connection) -> AppResult<Option<String>> {
-    let current_stack_id = get_current_stack_id(db)?;
-    let maybe_task_id: Option<i64> = db.query_row("SELECT id
-    FROM tasks
-    WHERE task_order = (SELECT max(task_order) FROM tasks WHERE stack_id = ?)
-    AND stack_id = ?", params![current_stack_id, current_stack_id], |row| row.get(0)).optional()?;
+/// Get the order of a task going on top of the tasks in `context`.
+fn top_order<S: Store>(store: &S, context: Context) -> AppResult<f64> {
+    Ok(store.task_order_range(context)?.map_or(1., |(_, max)| max + 1.))
+}
 
-    if let Some(task_id) = maybe_task_id {
-        let task: String = db.query_row("SELECT task FROM tasks WHERE id = ?", params![task_id], |row| row.get(0))?;
-        db.execute("DELETE FROM tasks WHERE id = ?", params![task_id])?;
-        Ok(Some(task))
-    } else {
-        Ok(None)
+/// Get the order of a task going underneath the tasks in `context`.
+fn bottom_order<S: Store>(store: &S, context: Context) -> AppResult<f64> {
+    Ok(store.task_order_range(context)?.map_or(1., |(min, _)| min - 1.))
+}
+
+/// Get `count` evenly spaced orders for tasks going right above the task
+/// `after`, and below the next task up in its (sub-)stack that isn't in `skip`.
+///
+/// If they'd be too close together, the (sub-)stack is renormalized first.
+fn orders_above<S: Store>(store: &mut S, after_id: TaskId, skip: &[TaskId], count: usize) -> AppResult<Vec<f64>> {
+    let neighbours = |store: &S| -> AppResult<(f64, Option<f64>)> {
+        let after = get_task_record(store, after_id)?;
+        let next_order = store.open_tasks(after.context())?.into_iter()
+            .filter(|task| !skip.contains(&task.id))
+            .map(|task| task.order)
+            .find(|order| *order > after.order);
+        Ok((after.order, next_order))
+    };
+    let gap = |(after_order, next_order): (f64, Option<f64>)| next_order.map_or(1., |next_order| (next_order - after_order) / (count + 1) as f64);
+
+    let mut range = neighbours(store)?;
+    if gap(range) < MIN_ORDER_GAP {
+        renormalize(store, get_task_record(store, after_id)?.context())?;
+        range = neighbours(store)?;
     }
+    let step = gap(range);
+    Ok((1..=count).map(|i| range.0 + step * i as f64).collect())
 }
 
-/// Clear all tasks from the current stack.
-pub fn clear_tasks(db: &Connection) -> AppResult<()> {
-    let current_stack_id = get_current_stack_id(db)?;
-    db.execute("DELETE FROM tasks WHERE stack_id = ?", params![current_stack_id])?;
-    Ok(())
+/// Spread the orders of the open tasks in `context` out evenly, as 1, 2, 3 and
+/// so on, without changing their order.
+///
+/// Returns the number of tasks whose order changed.
+pub fn renormalize<S: Store>(store: &mut S, context: Context) -> AppResult<usize> {
+    store.transaction(|store| {
+        let mut changed = 0;
+        for (i, mut task) in store.open_tasks(context)?.into_iter().enumerate() {
+            let order = (i + 1) as f64;
+            if task.order != order {
+                task.order = order;
+                store.update_task(&task)?;
+                changed += 1;
+            }
+        }
+        Ok(changed)
+    })
 }
 
-/// Clear all tasks from all stacks.
-pub fn clear_all_tasks(db: &Connection) -> AppResult<()> {
-    db.execute("DELETE FROM tasks WHERE 1 = 1", [])?;
-    Ok(())
+/// Renormalize every stack and sub-stack.
+///
+/// Returns the number of tasks whose order changed.
+pub fn compact_orders<S: Store>(store: &mut S) -> AppResult<usize> {
+    store.transaction(|store| {
+        let mut changed = 0;
+        for stack in store.stacks()? {
+            changed += renormalize(store, Context { stack_id: stack.id, parent_id: None })?;
+            for task in store.open_tasks_on_stack(stack.id)? {
+                changed += renormalize(store, Context { stack_id: stack.id, parent_id: Some(task.id) })?;
+            }
+        }
+        Ok(changed)
+    })
 }
 
-/// Insert `task` after the `task_index`th task, starting from 0.
-/// 
-/// i.e. if `task_index == 0`, then this is equivalent to `backpush`
-pub fn insert_after(db: &mut Connection, task_index: TaskIndex, task: String) -> AppResult<()> {
-    // two cases: task is last and task is not last
-    // if task is not last, avg() works
-    // if task is last, avg() just gives task order
-    let current_stack_id = get_current_stack_id(db)?;
-    let num_tasks = db.query_row("SELECT count(*) FROM tasks WHERE stack_id = ?", params![current_stack_id], |row| row.get(0))?;
-    if task_index >= num_tasks {
-        return Err(TaskError::NoSuchTask(task_index).into());
-    } else if task_index == num_tasks - 1 {
-        return Ok(push_task(db, task)?);
-    }
-
-    assert!(num_tasks > 1);
-    let task_id = task_index_to_task_id(db, current_stack_id, task_index)?;
-    let task_order: f64 = db.query_row("SELECT task_order FROM tasks WHERE id = ?", params![task_id], |row| row.get(0))?;
-    let task_orders = {
-        let mut task_order_query = db.prepare("SELECT task_order FROM tasks WHERE stack_id = ? AND task_order >= ? ORDER BY task_order ASC LIMIT 2")?;
-        let task_orders = task_order_query.query_map(params![current_stack_id, task_order], |row| row.get(0))?.collect::<Result<Vec<f64>, _>>()?;
-        task_orders
+/// Get the top task in `context`, if it has any tasks.
+fn top_task<S: Store>(store: &S, context: Context) -> AppResult<Option<TaskRecord>> {
+    Ok(store.open_tasks(context)?.pop())
+}
+
+/// Count the open tasks in `context`.
+fn count_tasks<S: Store>(store: &S, context: Context) -> AppResult<TaskIndex> {
+    Ok(store.open_tasks(context)?.len() as TaskIndex)
+}
+
+/// Pop the top task off the stack, marking it done.
+///
+/// Popping the last task of a sub-stack returns to the enclosing stack, so its
+/// owner is on top again. Tasks with open sub-tasks can't be popped.
+pub fn pop_task<S: Store>(store: &mut S) -> AppResult<Option<Task>> {
+    let context = get_current_context(store)?;
+    let Some(mut task) = top_task(store, context)? else {
+        return Ok(None);
     };
-    assert!(task_orders.len() == 2);
-    let mut new_order = 0.;
-    for o in &task_orders {
-        new_order += o;
+
+    let subtasks = count_tasks(store, Context { parent_id: Some(task.id), ..context })?;
+    if subtasks > 0 {
+        return Err(TaskError::HasOpenSubtasks(task.task, subtasks).into());
+    }
+    task.status = TaskStatus::Done;
+    task.finished_at = Some(now());
+    store.update_task(&task)?;
+    if context.parent_id.is_some() && count_tasks(store, context)? == 0 {
+        surface(store)?;
+    }
+    Ok(Some(get_task(store, task.id)?))
+}
+
+/// Get the task `depth` places below the top of `context`, the top task being 0.
+fn task_from_top<S: Store>(store: &S, context: Context, depth: TaskIndex) -> AppResult<TaskRecord> {
+    let mut tasks = store.open_tasks(context)?;
+    let count = tasks.len() as TaskIndex;
+    if count == 0 {
+        return Err(TaskError::NoTasks.into());
+    } else if depth >= count {
+        return Err(TaskError::NotEnoughTasks(depth + 1, count).into());
     }
-    new_order /= task_orders.len() as f64;
-    let new_order = new_order;
-    db.execute("INSERT INTO tasks(task, task_order, stack_id) VALUES (?, ?, ?)", params![task, new_order, current_stack_id])?;
+    Ok(tasks.remove((count - 1 - depth) as usize))
+}
+
+/// Get the top task without popping it.
+pub fn peek_task<S: Store>(store: &S) -> AppResult<Task> {
+    Ok(list_tasks(store)?.pop().ok_or(TaskError::NoTasks)?)
+}
+
+/// Bring the task `depth` places below the top up to the top. `rot` is a roll of 2.
+pub fn roll_task<S: Store>(store: &mut S, depth: TaskIndex) -> AppResult<Task> {
+    let context = get_current_context(store)?;
+    let mut task = task_from_top(store, context, depth)?;
+    if depth > 0 {
+        task.order = top_order(store, context)?;
+        store.update_task(&task)?;
+    }
+    get_task(store, task.id)
+}
+
+/// Send the top task to the bottom of the stack.
+pub fn bury_task<S: Store>(store: &mut S) -> AppResult<Task> {
+    let context = get_current_context(store)?;
+    let mut task = task_from_top(store, context, 0)?;
+    task.order = bottom_order(store, context)?;
+    store.update_task(&task)?;
+    get_task(store, task.id)
+}
+
+/// Push a copy of the task `depth` places below the top, with its tags: `dup`
+/// copies the top task, `over` the one below it. Sub-tasks and reminders aren't copied.
+pub fn dup_task<S: Store>(store: &mut S, depth: TaskIndex) -> AppResult<Task> {
+    let context = get_current_context(store)?;
+    let task = task_from_top(store, context, depth)?;
+    let copy_id = store.transaction(|store| {
+        let copy = push_task(store, task.task)?;
+        for tag in store.tags()?.into_iter().filter(|tag| tag.task_id == task.id) {
+            store.insert_tag(&TagRecord { task_id: copy.id, tag: tag.tag })?;
+        }
+        Ok(copy.id)
+    })?;
+    get_task(store, copy_id)
+}
+
+/// Make the sub-stack of the top task the current context.
+///
+/// Returns the task dived into.
+pub fn dive<S: Store>(store: &mut S) -> AppResult<Task> {
+    let context = get_current_context(store)?;
+    let task = top_task(store, context)?.ok_or(TaskError::NoTasks)?;
+    store.set_context(Context { parent_id: Some(task.id), ..context })?;
+    get_task(store, task.id)
+}
+
+/// Return from the current sub-stack to the enclosing stack.
+///
+/// Returns the task whose sub-stack was left.
+pub fn surface<S: Store>(store: &mut S) -> AppResult<Task> {
+    let context = get_current_context(store)?;
+    let parent_id = context.parent_id.ok_or(TaskError::NotInSubstack)?;
+    let parent = get_task_record(store, parent_id)?;
+    store.set_context(Context { parent_id: parent.parent_id, ..context })?;
+    get_task(store, parent_id)
+}
 
+/// Mark the open task `task`, and all its open sub-tasks, abandoned.
+fn abandon_subtree<S: Store>(store: &mut S, mut task: TaskRecord, finished_at: Timestamp) -> AppResult<()> {
+    for subtask in store.open_tasks(Context { parent_id: Some(task.id), ..task.context() })? {
+        abandon_subtree(store, subtask, finished_at)?;
+    }
+    task.status = TaskStatus::Abandoned;
+    task.finished_at = Some(finished_at);
+    store.update_task(&task)
+}
+
+/// Clear all tasks from the current stack, marking them abandoned.
+pub fn clear_tasks<S: Store>(store: &mut S) -> AppResult<()> {
+    let context = get_current_context(store)?;
+    let finished_at = now();
+    for task in store.open_tasks(context)? {
+        abandon_subtree(store, task, finished_at)?;
+    }
     Ok(())
 }
 
+/// Clear all tasks from all stacks, marking them abandoned.
+pub fn clear_all_tasks<S: Store>(store: &mut S) -> AppResult<()> {
+    let finished_at = now();
+    for stack in store.stacks()? {
+        for mut task in store.open_tasks_on_stack(stack.id)? {
+            task.status = TaskStatus::Abandoned;
+            task.finished_at = Some(finished_at);
+            store.update_task(&task)?;
+        }
+    }
+    let context = get_current_context(store)?;
+    store.set_context(Context { parent_id: None, ..context })
+}
+
+/// Insert `task` right above the task `after`, in whichever (sub-)stack `after` is in.
+pub fn insert_after<S: Store>(store: &mut S, after: TaskRef, task: String) -> AppResult<Task> {
+    let (after_id, context) = resolve_task_ref(store, after)?;
+    store.transaction(|store| {
+        // Go halfway to the next task up, or one above `after` if it's the top task.
+        let order = orders_above(store, after_id, &[], 1)?[0];
+        insert_task(store, task, order, context)
+    })
+}
+
 /// Pop the current task and push it onto `destination_stack`.
-pub fn pop_to(db: &Connection, destination_stack: String) -> AppResult<()> {
-    let current_stack_id = get_current_stack_id(db)?;
-    let destination_stack_id = stack_name_to_id(db, &destination_stack)?;
-    let maybe_top_task_id: Option<u32> = db.query_row("SELECT id FROM tasks WHERE task_order = (SELECT max(task_order) FROM tasks WHERE stack_id = :stack_id) WHERE stack_id = :stack_id",
-    named_params! {":stack_id": current_stack_id}, |row| row.get(0)).optional()?;
-    if let Some(task_id) = maybe_top_task_id {
-        db.execute("UPDATE tasks SET stack_id = :stack_id WHERE id = :task_id", named_params! {":stack_id": destination_stack_id, ":task_id": task_id})?;
+///
+/// The task's sub-tasks move with it. Returns the moved task, if there was one.
+pub fn pop_to<S: Store>(store: &mut S, destination_stack: String) -> AppResult<Option<Task>> {
+    let context = get_current_context(store)?;
+    let Some(task) = top_task(store, context)? else {
+        stack_name_to_id(store, &destination_stack)?;
+        return Ok(None);
+    };
+    let moved = move_tasks(store, TaskRef::Id(task.id).into(), destination_stack, MovePosition::Top)?;
+    Ok(moved.into_iter().next())
+}
+
+/// Move the tasks in `range`, which must be in the same (sub-)stack, to `position` on `destination_stack`.
+///
+/// The tasks keep their order, and their sub-tasks and reminders move with
+/// them. Returns the moved tasks, bottom first.
+pub fn move_tasks<S: Store>(store: &mut S, range: TaskRange, destination_stack: String, position: MovePosition) -> AppResult<Vec<Task>> {
+    let (start_id, context) = resolve_task_ref(store, range.start)?;
+    let (end_id, end_context) = resolve_task_ref(store, range.end)?;
+    if context != end_context {
+        return Err(TaskError::NotOnSameStack(range.start, range.end).into());
     }
-    Ok(())
+    let destination_stack_id = stack_name_to_id(store, &destination_stack)?;
+
+    let siblings = store.open_tasks(context)?;
+    let start = siblings.iter().position(|task| task.id == start_id).expect("bug: task isn't in its own stack");
+    let end = siblings.iter().position(|task| task.id == end_id).expect("bug: task isn't in its own stack");
+    let moving: Vec<TaskRecord> = siblings[start.min(end)..=start.max(end)].to_vec();
+    let moving_ids: Vec<TaskId> = moving.iter().map(|task| task.id).collect();
+    // Whether `task_id` is one of the moving tasks or inside one of their sub-stacks.
+    let is_moving = |store: &S, mut task_id: Option<TaskId>| -> AppResult<bool> {
+        while let Some(id) = task_id {
+            if moving_ids.contains(&id) {
+                return Ok(true);
+            }
+            task_id = get_task_record(store, id)?.parent_id;
+        }
+        Ok(false)
+    };
+
+    let top_level = Context { stack_id: destination_stack_id, parent_id: None };
+    let after_id = match position {
+        MovePosition::After(after) => {
+            let after_id = match after {
+                TaskRef::Index(index) => task_index_to_task_id(store, top_level, index)?,
+                TaskRef::Id(id) => match store.task(id)? {
+                    Some(task) if task.status == TaskStatus::Open && task.stack_id == destination_stack_id => id,
+                    _ => return Err(TaskError::NoSuchTask(after).into()),
+                }
+            };
+            if is_moving(store, Some(after_id))? {
+                return Err(TaskError::MoveAfterItself(after).into());
+            }
+            Some(after_id)
+        }
+        MovePosition::Top | MovePosition::Bottom => None,
+    };
+
+    store.transaction(|store| {
+        let count = moving.len();
+        let (destination, orders) = match (position, after_id) {
+            (_, Some(after_id)) => (get_task_record(store, after_id)?.context(), orders_above(store, after_id, &moving_ids, count)?),
+            (MovePosition::Bottom, None) => {
+                let bottom = bottom_order(store, top_level)?;
+                (top_level, (0..count).map(|i| bottom - (count - 1 - i) as f64).collect())
+            }
+            (_, None) => {
+                let top = top_order(store, top_level)?;
+                (top_level, (0..count).map(|i| top + i as f64).collect())
+            }
+        };
+        for (mut task, order) in moving.into_iter().zip(orders) {
+            let mut subtasks = store.subtasks(task.id)?;
+            while let Some(mut subtask) = subtasks.pop() {
+                subtasks.extend(store.subtasks(subtask.id)?);
+                subtask.stack_id = destination_stack_id;
+                store.update_task(&subtask)?;
+            }
+            task.order = order;
+            task.stack_id = destination.stack_id;
+            task.parent_id = destination.parent_id;
+            store.update_task(&task)?;
+        }
+        // Moving a task by ID can take the sub-stack we're in with it.
+        let current = get_current_context(store)?;
+        if is_moving(store, current.parent_id)? {
+            store.set_context(Context { stack_id: destination_stack_id, ..current })?;
+        }
+        Ok(())
+    })?;
+    moving_ids.iter().map(|id| get_task(store, *id)).collect()
 }
 
 /// Create a new stack called `stack_name`.
-/// 
+///
 /// Returns an error if the stack already exists.
-pub fn new_stack(db: &Connection, stack_name: String) -> AppResult<()> {
-    let stack_exists: Option<i32> = db.query_row("SELECT 1 FROM stacks WHERE name = ?", params![stack_name], |row| row.get(0)).optional()?;
-    if let Some(_) = stack_exists {
+pub fn new_stack<S: Store>(store: &mut S, stack_name: String) -> AppResult<Stack> {
+    if store.stack_by_name(&stack_name)?.is_some() {
         return Err(StackError::StackAlreadyExists(stack_name).into());
     }
 
-    db.execute("INSERT INTO stacks(name) VALUES (?)", params![stack_name])?;
-    Ok(())
+    store.insert_stack(&stack_name)?;
+    get_stack(store, &stack_name)
+}
+
+/// Get the stack called `name`.
+pub fn get_stack<S: Store>(store: &S, name: &str) -> AppResult<Stack> {
+    list_stacks(store)?.into_iter()
+        .find(|stack| stack.name == name)
+        .ok_or_else(|| StackError::NoSuchStack(name.into()).into())
 }
 
 /// Convert a stack name into an ID.
 ///
 /// Returns an error if `name` does not refer to an existing stack.
-fn stack_name_to_id(db: &Connection, name: &str) -> AppResult<StackId> {
-    let maybe_stack_id: Option<StackId> = db.query_row("SELECT id FROM stacks WHERE name = ?",
-        params![name], |row| row.get(0)).optional()?;
-    match maybe_stack_id {
-        None => return Err(StackError::NoSuchStack(name.into()).into()),
-        Some(id) => Ok(id)
+pub fn stack_name_to_id<S: Store>(store: &S, name: &str) -> AppResult<StackId> {
+    match store.stack_by_name(name)? {
+        None => Err(StackError::NoSuchStack(name.into()).into()),
+        Some(stack) => Ok(stack.id)
     }
 }
 
-/// Drop a stack and all tasks in it.
-pub fn drop_stack(db: &mut Connection, stack_name: String) -> AppResult<()> {
-    let current_stack_id = get_current_stack_id(db)?;
-    let stack_id = stack_name_to_id(db, &stack_name)?;
+/// Drop a stack and all tasks in it, including its history.
+pub fn drop_stack<S: Store>(store: &mut S, stack_name: String) -> AppResult<()> {
+    let current_stack_id = get_current_stack_id(store)?;
+    let stack_id = stack_name_to_id(store, &stack_name)?;
     if stack_id == DEFAULT_STACK_ID {
         return Err(StackError::CantDeleteDefaultStack.into());
     } else if stack_id == current_stack_id {
         return Err(StackError::CantDeleteCurrentStack.into());
     }
-    let xact = db.transaction()?;
-    xact.execute("DELETE FROM tasks WHERE stack_id = ?", params![stack_id])?;
-    xact.execute("DELETE FROM stacks WHERE id = ?", params![stack_id])?;
-    xact.commit()?;
-    Ok(())
+    store.transaction(|store| store.delete_stack(stack_id))
+}
+
+/// Rename the stack `old_name` to `new_name`.
+pub fn rename_stack<S: Store>(store: &mut S, old_name: &str, new_name: String) -> AppResult<Stack> {
+    let Some(mut stack) = store.stack_by_name(old_name)? else {
+        return Err(StackError::NoSuchStack(old_name.into()).into());
+    };
+    if store.stack_by_name(&new_name)?.is_some() {
+        return Err(StackError::StackAlreadyExists(new_name).into());
+    }
+
+    stack.name = new_name;
+    store.transaction(|store| store.update_stack(&stack))?;
+    get_stack(store, &stack.name)
+}
+
+/// Copy the open tasks on `source`, and their pending reminders, to a new stack called `destination`.
+///
+/// Returns the new stack and the IDs of the copied reminders, which still have to be scheduled.
+pub fn copy_stack<S: Store>(store: &mut S, source: &str, destination: String) -> AppResult<(Stack, Vec<String>)> {
+    let source_id = stack_name_to_id(store, source)?;
+    if store.stack_by_name(&destination)?.is_some() {
+        return Err(StackError::StackAlreadyExists(destination).into());
+    }
+
+    let mut document = export::export(store, Some(source_id))?;
+    document.current_stack = None;
+    for stack in &mut document.stacks {
+        stack.name = destination.clone();
+        stack.history.clear();
+    }
+    let reminder_ids = export::import(store, &document, ImportMode::Merge)?;
+    Ok((get_stack(store, &destination)?, reminder_ids))
+}
+
+/// Move every task on `source`, with its reminders, onto `destination`, and drop `source`.
+///
+/// The tasks keep their order and go on top of or below the tasks already on
+/// `destination`. The default stack can't be dropped, so it's left empty instead.
+/// Bindings and the current stack move to `destination` along with the tasks.
+pub fn merge_stack<S: Store>(store: &mut S, source: &str, destination: &str, position: MergePosition) -> AppResult<()> {
+    let source_id = stack_name_to_id(store, source)?;
+    let destination_id = stack_name_to_id(store, destination)?;
+    if source_id == destination_id {
+        return Err(StackError::MergeIntoItself(source.into()).into());
+    }
+
+    store.transaction(|store| {
+        let open_tasks = store.open_tasks_on_stack(source_id)?;
+        let top_level_count = open_tasks.iter().filter(|task| task.parent_id.is_none()).count();
+        let top_level = Context { stack_id: destination_id, parent_id: None };
+        let mut next_order = match position {
+            MergePosition::OnTop => top_order(store, top_level)?,
+            MergePosition::Below => bottom_order(store, top_level)? + 1. - top_level_count as f64,
+        };
+        // Sub-stacks move with their parents, so only top-level tasks need new orders.
+        for mut task in open_tasks {
+            if task.parent_id.is_none() {
+                task.order = next_order;
+                next_order += 1.;
+            }
+            task.stack_id = destination_id;
+            store.update_task(&task)?;
+        }
+        for mut task in store.finished_tasks(Some(source_id), None, None)? {
+            task.stack_id = destination_id;
+            store.update_task(&task)?;
+        }
+
+        for mut binding in store.bindings()?.into_iter().filter(|binding| binding.stack_id == source_id) {
+            binding.stack_id = destination_id;
+            store.set_binding(&binding)?;
+        }
+        let mut context = store.context()?;
+        if context.stack_id == source_id {
+            context.stack_id = destination_id;
+            store.set_context(context)?;
+        }
+        if source_id != DEFAULT_STACK_ID {
+            store.delete_stack(source_id)?;
+        }
+        Ok(())
+    })
 }
 
 /// Switch to the stack `stack_name`.
-pub fn switch_to_stack(db: &Connection, stack_name: String) -> AppResult<()> {
-    let stack_id = stack_name_to_id(db, &stack_name)?;
-    db.execute("UPDATE app_state SET stack_id = ?", params![stack_id])?;
-    Ok(())
+pub fn switch_to_stack<S: Store>(store: &mut S, stack_name: String) -> AppResult<()> {
+    let stack_id = stack_name_to_id(store, &stack_name)?;
+    store.set_context(Context { stack_id, parent_id: None })
+}
+
+/// Turn a stored binding into a [`Binding`].
+fn binding_from_record<S: Store>(store: &S, record: BindingRecord) -> AppResult<Binding> {
+    let stack = store.stacks()?.into_iter().find(|stack| stack.id == record.stack_id).expect("bug: binding to a stack that doesn't exist");
+    Ok(Binding { path: record.path, stack: stack.name })
+}
+
+/// Find the binding that applies in `dir`: the one for `dir` itself, or else for its nearest ancestor.
+pub fn find_binding<S: Store>(store: &S, dir: &Path) -> AppResult<Option<Binding>> {
+    let nearest = store.bindings()?.into_iter()
+        .filter(|binding| dir.starts_with(&binding.path))
+        .max_by_key(|binding| binding.path.components().count());
+    nearest.map(|record| binding_from_record(store, record)).transpose()
+}
+
+/// Bind the stack `stack_name` to `dir`, replacing any stack bound to it.
+pub fn bind_stack<S: Store>(store: &mut S, stack_name: String, dir: &Path) -> AppResult<Binding> {
+    let stack_id = stack_name_to_id(store, &stack_name)?;
+    store.set_binding(&BindingRecord { path: dir.to_path_buf(), stack_id })?;
+    Ok(Binding { path: dir.to_path_buf(), stack: stack_name })
+}
+
+/// Remove the binding that applies in `dir`, returning it.
+pub fn unbind<S: Store>(store: &mut S, dir: &Path) -> AppResult<Binding> {
+    let binding = find_binding(store, dir)?.ok_or_else(|| StackError::NotBound(dir.display().to_string()))?;
+    store.delete_binding(&binding.path)?;
+    Ok(binding)
+}
+
+/// Switch to the stack bound to `dir`, if there is one and it isn't current already.
+///
+/// Returns the binding that applies in `dir`.
+pub fn use_binding<S: Store>(store: &mut S, dir: &Path) -> AppResult<Option<Binding>> {
+    let Some(binding) = find_binding(store, dir)? else {
+        return Ok(None);
+    };
+    if get_current_stack_name(store)? != binding.stack {
+        switch_to_stack(store, binding.stack.clone())?;
+    }
+    Ok(Some(binding))
+}
+
+/// List tasks finished between `since` and `until`, most recent first.
+///
+/// If `stack_id` is `None`, tasks from all stacks are listed.
+pub fn list_finished_tasks<S: Store>(store: &S, stack_id: Option<StackId>, since: Option<Timestamp>, until: Option<Timestamp>) -> AppResult<Vec<Task>> {
+    let records = store.finished_tasks(stack_id, since, until)?;
+    tasks_from_records(store, records, false)
 }
 
 /// List all stacks.
-pub fn list_stacks(db: &Connection) -> RusqliteResult<Vec<String>> {
-    let mut stmt = db.prepare("SELECT name FROM stacks")?;
-    let result = stmt.query_map([], |row| row.get(0))?.collect();
-    result
+pub fn list_stacks<S: Store>(store: &S) -> AppResult<Vec<Stack>> {
+    let current_stack_id = get_current_stack_id(store)?;
+    let mut stacks = Vec::new();
+    for stack in store.stacks()? {
+        let tasks = tasks_from_records(store, store.open_tasks_on_stack(stack.id)?, false)?;
+        let mut tags: BTreeMap<String, u64> = BTreeMap::new();
+        for tag in tasks.iter().flat_map(|task| &task.tags) {
+            *tags.entry(tag.clone()).or_default() += 1;
+        }
+        stacks.push(Stack {
+            tasks: tasks.len() as u64,
+            tags,
+            current: stack.id == current_stack_id,
+            id: stack.id,
+            name: stack.name,
+        });
+    }
+    Ok(stacks)
 }
 
+/// List the tasks in the current context, bottom first.
+pub fn list_tasks<S: Store>(store: &S) -> AppResult<Vec<Task>> {
+    let context = get_current_context(store)?;
+    let records = store.open_tasks(context)?;
+    tasks_from_records(store, records, true)
+}
 
-pub fn list_tasks(db: &Connection) -> AppResult<Vec<String>> {
-    let current_stack_id = get_current_stack_id(db)?;
-    let mut stmt = db.prepare("SELECT task FROM tasks WHERE stack_id = ? ORDER BY task_order")?;
+/// List the open tasks on every stack, sub-stacks included, that pass `filter`.
+///
+/// Tasks are listed by stack, oldest stack first, and then by order.
+pub fn find_tasks<S: Store>(store: &S, filter: &TagFilter) -> AppResult<Vec<Task>> {
     let mut tasks = Vec::new();
-    let rows = stmt.query_map(params![current_stack_id], |row| row.get(0))?;
-    for row in rows {
-        tasks.push(row?);
+    for stack in store.stacks()? {
+        let records = store.open_tasks_on_stack(stack.id)?;
+        tasks.extend(tasks_from_records(store, records, false)?.into_iter().filter(|task| filter.matches(task)));
     }
     Ok(tasks)
 }
 
-pub fn swap_tasks(db: &mut Connection, idx1: TaskIndex, idx2: TaskIndex) -> AppResult<()> {
-    let current_stack_id = get_current_stack_id(db)?;
-    let task_count: TaskIndex = db.query_row("SELECT count(*) FROM tasks WHERE stack_id = ?", params![current_stack_id], |row| row.get(0))?;
-    match (idx1 >= task_count, idx2 >= task_count) {
-        (false, false) => {}
-        (true, false) | (false, true) => {
-            let invalid = cmp::max(idx1, idx2);
-            return Err(TaskError::NoSuchTask(invalid).into());
-        }
-        (true, true) => {
-            return Err(TaskError::NoSuchTasks(idx1, idx2).into());
+/// List every open task on the current stack, with each task's sub-tasks following it.
+pub fn list_task_tree<S: Store>(store: &S) -> AppResult<Vec<TreeTask>> {
+    let context = get_current_context(store)?;
+    let records = store.open_tasks_on_stack(context.stack_id)?;
+    let parent_ids: Vec<Option<TaskId>> = records.iter().map(|record| record.parent_id).collect();
+    let tasks: Vec<(Task, Option<TaskId>)> = tasks_from_records(store, records, false)?.into_iter().zip(parent_ids).collect();
+
+    fn visit(tasks: &[(Task, Option<TaskId>)], parent_id: Option<TaskId>, depth: usize, current: Option<TaskId>, tree: &mut Vec<TreeTask>) {
+        let children = tasks.iter().filter(|(_, parent)| *parent == parent_id);
+        for (index, (task, _)) in children.enumerate() {
+            tree.push(TreeTask {
+                task: Task { index: Some(index as TaskIndex), ..task.clone() },
+                depth,
+                is_current: current == Some(task.id),
+            });
+            visit(tasks, Some(task.id), depth + 1, current, tree);
         }
     }
+    let mut tree = Vec::new();
+    visit(&tasks, None, 0, context.parent_id, &mut tree);
+    Ok(tree)
+}
 
-    let (min, max) = (cmp::min(idx1, idx2), cmp::max(idx1, idx2));
-    let min_id = task_index_to_task_id(db, current_stack_id, min)?;
-    let max_id = task_index_to_task_id(db, current_stack_id, max)?;
-    let min_order: i32 = db.query_row("SELECT task_order FROM tasks WHERE stack_id = ? AND id = ?", params![current_stack_id, min_id], |r| r.get(0))?;
-    let max_order: i32 = db.query_row("SELECT task_order FROM tasks WHERE stack_id = ? AND id = ?", params![current_stack_id, max_id], |r| r.get(0))?;
-    let xact = db.transaction()?;
-    xact.execute("UPDATE tasks SET task_order = ? WHERE id = ?", params![max_order, min_id])?;
-    xact.execute("UPDATE tasks SET task_order = ? WHERE id = ?", params![min_order, max_id])?;
-    xact.commit()?;
+/// Swap the positions of two tasks in the same (sub-)stack.
+pub fn swap_tasks<S: Store>(store: &mut S, task1: TaskRef, task2: TaskRef) -> AppResult<()> {
+    let ((id1, context1), (id2, context2)) = match (resolve_task_ref(store, task1), resolve_task_ref(store, task2)) {
+        (Ok(resolved1), Ok(resolved2)) => (resolved1, resolved2),
+        (Err(AppError::Task(TaskError::NoSuchTask(_))), Err(AppError::Task(TaskError::NoSuchTask(_)))) => {
+            return Err(TaskError::NoSuchTasks(task1, task2).into());
+        }
+        (Err(e), _) | (_, Err(e)) => return Err(e),
+    };
+    if context1 != context2 {
+        return Err(TaskError::NotOnSameStack(task1, task2).into());
+    }
 
-    Ok(())
+    let mut record1 = get_task_record(store, id1)?;
+    let mut record2 = get_task_record(store, id2)?;
+    std::mem::swap(&mut record1.order, &mut record2.order);
+    store.transaction(|store| {
+        store.update_task(&record1)?;
+        store.update_task(&record2)
+    })
 }
 
-fn task_index_to_task_id(db: &mut Connection, stack_id: StackId, task_index: TaskIndex) -> AppResult<i32> {
-    let task_count: TaskIndex = db.query_row("SELECT count(*) FROM tasks WHERE stack_id = ?", params![stack_id], |row| row.get(0))?;
-    if task_index >= task_count {
-        return Err(TaskError::NoSuchTask(task_index).into());
+fn task_index_to_task_id<S: Store>(store: &S, context: Context, task_index: TaskIndex) -> AppResult<TaskId> {
+    let tasks = store.open_tasks(context)?;
+    match tasks.get(task_index as usize) {
+        Some(task) => Ok(task.id),
+        None => Err(TaskError::NoSuchTask(TaskRef::Index(task_index)).into()),
     }
+}
 
-    let id = db.query_row("SELECT id FROM (SELECT id, row_number() OVER (ORDER BY task_order) row FROM tasks WHERE stack_id = ?) WHERE row = (? + 1)",
-    params![stack_id, task_index], 
-    |row| row.get(0))?;
-    Ok(id)
+/// Find the open task `task_ref` refers to, and the (sub-)stack it is in.
+///
+/// Indices count from the bottom of the current context; IDs can refer to a
+/// task on any stack.
+fn resolve_task_ref<S: Store>(store: &S, task_ref: TaskRef) -> AppResult<(TaskId, Context)> {
+    match task_ref {
+        TaskRef::Index(index) => {
+            let context = get_current_context(store)?;
+            Ok((task_index_to_task_id(store, context, index)?, context))
+        }
+        TaskRef::Id(id) => match store.task(id)? {
+            Some(task) if task.status == TaskStatus::Open => Ok((id, task.context())),
+            _ => Err(TaskError::NoSuchTask(task_ref).into()),
+        }
+    }
 }
 
-pub fn kill_task(db: &mut Connection, idx: TaskIndex) -> AppResult<String> {
-    let current_stack_id = get_current_stack_id(db)?;
-    let task_count: TaskIndex = db.query_row("SELECT count(*) FROM tasks WHERE stack_id = ?", params![current_stack_id], |row| row.get(0))?;
-    if idx >= task_count {
-        return Err(TaskError::NoSuchTask(idx).into());
+/// Remove a task and its sub-tasks from the stack without completing them, marking them abandoned.
+pub fn kill_task<S: Store>(store: &mut S, task_ref: TaskRef) -> AppResult<Task> {
+    let (task_id, _) = resolve_task_ref(store, task_ref)?;
+    let task = get_task_record(store, task_id)?;
+    let killed_parent_id = task.parent_id;
+    abandon_subtree(store, task, now())?;
+
+    // Killing a task by ID can remove the sub-stack we're in.
+    let context = get_current_context(store)?;
+    if let Some(parent_id) = context.parent_id {
+        if get_task_record(store, parent_id)?.status != TaskStatus::Open {
+            store.set_context(Context { parent_id: killed_parent_id, ..context })?;
+        }
     }
-    let task_id = task_index_to_task_id(db, current_stack_id, idx)?;
-    let task_description = db.query_row("SELECT task FROM tasks WHERE stack_id = ? AND id = ?", params![current_stack_id, task_id], |row| row.get(0))?;
-    db.execute("DELETE FROM tasks WHERE stack_id = ? AND id = ?", params![current_stack_id, task_id])?;
 
-    Ok(task_description)
+    get_task(store, task_id)
 }
 
-fn parse_delay_spec_into_seconds(spec: &str) -> AppResult<u32> {
-    let spec_regex = Regex::new("(?P<amount>[1-9][0-9]{0,5})(?P<unit>[hms])").expect("bug: invalid regex in parse_delay_spec");
-    if !spec_regex.is_match(spec) {
-        return Err(ReminderError::InvalidReminderTime(spec.into()).into());
-    }
-    let caps = spec_regex.captures(spec).expect("bug: delay spec regex matches but cannot get captures");
-    let amount: u32 = caps.name("amount")
-        .expect("amount capture missing from delay spec regex")
-        .as_str()
-        .parse()
-        .expect("invalid number in delay spec");
-    let unit = caps.name("unit")
-        .expect("unit capture missing from delay spec regex")
-        .as_str();
-    let multiplier = match unit {
-        "h" => 60*60,
-        "m" => 60,
-        "s" => 1,
-        _ => unreachable!("Invalid unit: {}", unit)
-    };
-    Ok(amount.checked_mul(multiplier).expect("bug: overflow in delay time"))
+/// Remind the user of a task at the time `reminder_string`, parsed with [`timespec::parse`].
+///
+/// The reminder still has to be scheduled with [`schedule_reminder`].
+pub fn remind_me<S: Store>(store: &mut S, task_ref: TaskRef, reminder_string: String) -> AppResult<Reminder> {
+    let (task_id, _) = resolve_task_ref(store, task_ref)?;
+    let due_at = timespec::parse(&reminder_string, &Local::now())?.timestamp();
+    let reminder_id = Uuid::new_v4().to_string();
+    store.insert_reminder(&ReminderRecord { id: reminder_id.clone(), task_id, due_at, cancelled: false })?;
+    get_reminder(store, &reminder_id)
 }
 
-pub fn remind_me(db: &mut Connection, task_index: TaskIndex, reminder_string: String) -> AppResult<()> {
-    let current_stack_id = get_current_stack_id(db)?;
-    let task_id = task_index_to_task_id(db, current_stack_id, task_index)?;
-    let delay_time = parse_delay_spec_into_seconds(&reminder_string)?;
+/// Make sure the reminder `reminder_id` fires at its current due time.
+pub fn schedule_reminder(db_path: &Path, reminder_id: &str) -> AppResult<()> {
+    #[cfg(unix)]
+    if daemon::wake(db_path) {
+        return Ok(());
+    }
+    // No daemon, so wait for the reminder in a process of its own. A process
+    // already waiting for an earlier due time exits when it wakes up and sees
+    // the reminder was moved.
     let current_bin = env::current_exe().map_err(|e| AppError::Environment(format!("unable to obtain path to current executable: {}", e)))?;
-    // Lock the entire DB to prevent any other modifications
-    let xact = Transaction::new(db, rusqlite::TransactionBehavior::Exclusive)?;
-    let reminder_id = Uuid::new_v4().to_string();
-    xact.execute("INSERT INTO reminders(id, delay, task_id) VALUES (?, ?, ?)", params![reminder_id, delay_time, task_id])?;
-    // Potential race condition: We spawn the command before committing the transaction.
-    // To ensure this does not cause issues, lock the whole database (using an exclusive xact).
     Command::new(current_bin)
+        .arg("--db")
+        .arg(db_path)
         .arg("triggerreminder")
-        .arg(format!("{}", reminder_id))
+        .arg(reminder_id)
         .stdin(Stdio::null())
         .stdout(Stdio::null())
         .stderr(Stdio::null())
         .spawn()
         .map_err(|e| AppError::Environment(format!("unable to spawn reminder process: {}", e)))?;
-    xact.commit()?;
     // Do not wait on the process; let it run in the background
     Ok(())
 }
 
+/// Wait for the reminder `reminder_id` to come due, then fire it.
+///
+/// Snoozing or rescheduling the reminder starts another process for its new
+/// due time, so this one leaves it to that process once it sees the reminder
+/// was moved.
+pub fn trigger_reminder(db_path: PathBuf, mut store: SqliteStore, reminder_id: String) -> AppResult<()> {
+    let Some(due_at) = store.reminder(&reminder_id)?.map(|reminder| reminder.due_at) else {
+        return Ok(());
+    };
+    loop {
+        // A daemon or another process fired it already, or it was moved.
+        if store.reminder(&reminder_id)?.is_none_or(|reminder| reminder.due_at != due_at) {
+            return Ok(());
+        }
+        if due_at <= now() {
+            break;
+        }
+        // Close the DB connection, we don't want to hold onto it while waiting.
+        store.close()?;
+        thread::sleep(Duration::from_secs((due_at - now()).max(0) as u64));
+        // Check again, in case the reminder was snoozed or rescheduled meanwhile.
+        store = SqliteStore::open(&db_path)?;
+    }
+    // This deletes the reminder even if it was cancelled, but only fires it if it wasn't.
+    if let Some(task) = claim_reminder(&mut store, &reminder_id)? {
+        show_reminder(&task)?;
+    }
+    Ok(())
+}
+
+/// Find the pending reminder whose ID is or starts with `id_prefix`.
+fn resolve_reminder_id<S: Store>(store: &S, id_prefix: &str) -> AppResult<String> {
+    let ids: Vec<String> = store.reminders()?.into_iter()
+        .filter(|reminder| !reminder.cancelled && reminder.id.starts_with(id_prefix))
+        .map(|reminder| reminder.id)
+        .take(2)
+        .collect();
+    match &ids[..] {
+        [id] => Ok(id.clone()),
+        [] => Err(ReminderError::NoSuchReminder(id_prefix.into()).into()),
+        _ => Err(ReminderError::AmbiguousReminder(id_prefix.into()).into()),
+    }
+}
 
-pub fn trigger_reminder(db_path: PathBuf, db: Connection, reminder_id: String) -> AppResult<()> {
-    let (reminder_delay, task_id): (u32, i64) = db.query_row("SELECT delay, task_id FROM reminders WHERE id = ?", params![reminder_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
-    // Close the DB connection, we don't want to hold onto it while waiting.
-    if let Err((_, e)) = db.close() {
-        return Err(e.into());
+/// Get the pending reminder with ID `reminder_id`.
+fn get_reminder<S: Store>(store: &S, reminder_id: &str) -> AppResult<Reminder> {
+    let reminder = list_reminders(store)?.into_iter()
+        .find(|reminder| reminder.id == reminder_id)
+        .expect("bug: reminder disappeared");
+    Ok(reminder)
+}
+
+/// List pending reminders of open tasks on all stacks, soonest first.
+pub fn list_reminders<S: Store>(store: &S) -> AppResult<Vec<Reminder>> {
+    let mut reminders = Vec::new();
+    for reminder in store.reminders()?.into_iter().filter(|reminder| !reminder.cancelled) {
+        let task = get_task(store, reminder.task_id)?;
+        if task.status == TaskStatus::Open {
+            reminders.push(Reminder {
+                id: reminder.id,
+                task_id: task.id,
+                task: task.task,
+                stack: task.stack,
+                due_at: reminder.due_at,
+            });
+        }
     }
-    thread::sleep(Duration::from_secs(reminder_delay as u64));
-    let mut db = Connection::open(db_path)?;
-    let xact = db.transaction()?;
-    let task: String = xact.query_row("SELECT task FROM tasks WHERE id = ?", params![task_id], |row| row.get(0))?;
-    xact.execute("DELETE FROM reminders WHERE id = ?", params![reminder_id])?;
-    xact.commit()?;
+    Ok(reminders)
+}
+
+/// Cancel a pending reminder.
+///
+/// The reminder is kept until it would have fired, so processes waiting for
+/// it see that it was cancelled.
+pub fn cancel_reminder<S: Store>(store: &mut S, id_prefix: &str) -> AppResult<()> {
+    let reminder_id = resolve_reminder_id(store, id_prefix)?;
+    let mut reminder = store.reminder(&reminder_id)?.expect("bug: reminder disappeared");
+    reminder.cancelled = true;
+    store.update_reminder(&reminder)
+}
+
+/// Put a pending reminder off by the duration `delay`, counting from when it was due, or from now if it's overdue.
+///
+/// Returns the reminder's ID, to schedule it again with [`schedule_reminder`].
+pub fn snooze_reminder<S: Store>(store: &mut S, id_prefix: &str, delay: &str) -> AppResult<String> {
+    let reminder_id = resolve_reminder_id(store, id_prefix)?;
+    let delay_time = timespec::parse_duration(delay)?;
+    let mut reminder = store.reminder(&reminder_id)?.expect("bug: reminder disappeared");
+    reminder.due_at = reminder.due_at.max(now()) + delay_time;
+    store.update_reminder(&reminder)?;
+    Ok(reminder_id)
+}
+
+/// Make a pending reminder fire at the time `time_spec` instead, parsed with [`timespec::parse`].
+///
+/// Returns the reminder's ID, to schedule it again with [`schedule_reminder`].
+pub fn reschedule_reminder<S: Store>(store: &mut S, id_prefix: &str, time_spec: &str) -> AppResult<String> {
+    let reminder_id = resolve_reminder_id(store, id_prefix)?;
+    let mut reminder = store.reminder(&reminder_id)?.expect("bug: reminder disappeared");
+    reminder.due_at = timespec::parse(time_spec, &Local::now())?.timestamp();
+    store.update_reminder(&reminder)?;
+    Ok(reminder_id)
+}
+
+/// Get the ID and due time of the reminder due soonest, whether or not it was cancelled.
+pub fn next_reminder<S: Store>(store: &S) -> AppResult<Option<(String, Timestamp)>> {
+    Ok(store.reminders()?.into_iter().next().map(|reminder| (reminder.id, reminder.due_at)))
+}
+
+/// Take the reminder `reminder_id` out of the database once it's due, getting the task to remind about.
+///
+/// Whoever deletes the reminder gets to fire it, so it only fires once even if
+/// several processes are waiting for it. Returns `None` if it isn't due yet,
+/// someone else got there first, it was cancelled, or the task is finished and
+/// doesn't need reminding.
+pub fn claim_reminder<S: Store>(store: &mut S, reminder_id: &str) -> AppResult<Option<String>> {
+    store.transaction(|store| {
+        let Some(reminder) = store.reminder(reminder_id)?.filter(|reminder| reminder.due_at <= now()) else {
+            return Ok(None);
+        };
+        store.delete_reminder(reminder_id)?;
+        let task = get_task_record(store, reminder.task_id)?;
+        Ok((!reminder.cancelled && task.status == TaskStatus::Open).then_some(task.task))
+    })
+}
 
+/// Show a desktop notification reminding the user of `task`.
+pub fn show_reminder(task: &str) -> AppResult<()> {
     Notification::new()
         .summary("Task Reminder")
-        .body(&task)
+        .body(task)
         .timeout(Duration::from_secs(10))
         .show()
-        .expect("Failed to show notification");
+        .map_err(|e| AppError::Environment(format!("unable to show notification: {}", e)))?;
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Get the task being worked on: the top of the current (sub-)stack, or the
+/// task owning the sub-stack if it's empty.
+fn active_task<S: Store>(store: &S) -> AppResult<Option<TaskId>> {
+    let context = get_current_context(store)?;
+    Ok(top_task(store, context)?.map(|task| task.id).or(context.parent_id))
+}
+
+/// Start timing the task being worked on at `now`, stopping the task timed
+/// until then if it's a different one. Nothing is timed while paused.
+pub fn track_time<S: Store>(store: &mut S, now: Timestamp) -> AppResult<()> {
+    let active = match store.paused_at()? {
+        Some(_) => None,
+        None => active_task(store)?,
+    };
+    let running = store.running_time_entry()?;
+    // Entries whose task was deleted always stop.
+    if running.as_ref().map(|entry| entry.task_id) == active.map(Some) {
+        return Ok(());
+    }
+    store.transaction(|store| {
+        if let Some(entry) = running {
+            store.update_time_entry(&TimeEntryRecord { stopped_at: Some(now.max(entry.started_at)), ..entry })?;
+        }
+        if let Some(task_id) = active {
+            store.insert_time_entry(&TimeEntryRecord { id: 0, task_id: Some(task_id), started_at: now, stopped_at: None })?;
+        }
+        Ok(())
+    })
+}
+
+/// Pause time tracking at `now`, returning the task that was being timed.
+pub fn pause_timer<S: Store>(store: &mut S, now: Timestamp) -> AppResult<Option<Task>> {
+    if store.paused_at()?.is_some() {
+        return Err(TimeError::AlreadyPaused.into());
+    }
+    let running = store.running_time_entry()?;
+    store.set_paused_at(Some(now))?;
+    track_time(store, now)?;
+    running.and_then(|entry| entry.task_id).map(|task_id| get_task(store, task_id)).transpose()
+}
+
+/// Resume time tracking at `now`, returning the task now being timed.
+pub fn resume_timer<S: Store>(store: &mut S, now: Timestamp) -> AppResult<Option<Task>> {
+    if store.paused_at()?.is_none() {
+        return Err(TimeError::NotPaused.into());
+    }
+    store.set_paused_at(None)?;
+    track_time(store, now)?;
+    store.running_time_entry()?.and_then(|entry| entry.task_id).map(|task_id| get_task(store, task_id)).transpose()
+}
+
+/// Add up the time spent on tasks between `since` and `until`, counting the
+/// task being timed up to `now`.
+pub fn time_totals<S: Store>(store: &S, grouping: TimeGrouping, since: Option<Timestamp>, until: Option<Timestamp>, now: Timestamp) -> AppResult<Vec<TimeTotal>> {
+    let stacks: HashMap<StackId, String> = store.stacks()?.into_iter().map(|stack| (stack.id, stack.name)).collect();
+    let mut totals: BTreeMap<(String, Option<TaskId>), i64> = BTreeMap::new();
+    for entry in store.time_entries()? {
+        let start = since.map_or(entry.started_at, |since| entry.started_at.max(since));
+        let stop = until.map_or(entry.stopped_at.unwrap_or(now), |until| entry.stopped_at.unwrap_or(now).min(until));
+        if start >= stop {
+            continue;
+        }
+        let task = entry.task_id.map(|task_id| get_task_record(store, task_id)).transpose()?;
+        let spans = match (grouping, task) {
+            (TimeGrouping::Day, _) => split_by_day(start, stop).into_iter().map(|(day, seconds)| ((day, None), seconds)).collect(),
+            (_, None) => vec![((DELETED_TASKS.into(), None), stop - start)],
+            (TimeGrouping::Task, Some(task)) => vec![((task.task, Some(task.id)), stop - start)],
+            (TimeGrouping::Stack, Some(task)) => vec![((stacks[&task.stack_id].clone(), None), stop - start)],
+        };
+        for (key, seconds) in spans {
+            *totals.entry(key).or_default() += seconds;
+        }
+    }
+    let mut totals: Vec<TimeTotal> = totals.into_iter()
+        .map(|((name, task_id), seconds)| TimeTotal { name, task_id, seconds })
+        .collect();
+    if grouping != TimeGrouping::Day {
+        totals.sort_by_key(|total| std::cmp::Reverse(total.seconds));
+    }
+    Ok(totals)
+}
+
+/// Split the time from `start` to `stop` at local midnights, returning how
+/// much of it falls on each `YYYY-MM-DD` day.
+fn split_by_day(mut start: Timestamp, stop: Timestamp) -> Vec<(String, i64)> {
+    let mut days = Vec::new();
+    while start < stop {
+        let day = Local.timestamp_opt(start, 0).unwrap().date_naive();
+        let end = day.succ_opt().map(timespec::start_of_day).filter(|&midnight| midnight > start).map_or(stop, |midnight| midnight.min(stop));
+        days.push((day.format("%Y-%m-%d").to_string(), end - start));
+        start = end;
+    }
+    days
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDate;
+
+    /// Run each test against every store.
+    macro_rules! store_tests {
+        ($($test:ident),* $(,)?) => {
+            mod memory {
+                $(#[test] fn $test() { super::$test(&mut crate::store::MemoryStore::new()) })*
+            }
+            mod sqlite {
+                $(#[test] fn $test() { super::$test(&mut crate::migrations::test_db()) })*
+            }
+        };
+    }
+
+    store_tests!(
+        push_goes_on_top,
+        pushback_goes_on_bottom,
+        pop_takes_the_top_task,
+        finished_tasks_by_stack_and_time,
+        insert_after_goes_between,
+        insert_after_top_task,
+        repeated_insert_after_keeps_order,
+        swap_exchanges_positions,
+        swap_rejects_bad_refs,
+        ordering_is_per_substack,
+        orders_are_per_stack,
+        compact_spreads_orders_out,
+        popping_last_subtask_surfaces_parent,
+        kill_abandons_subtasks,
+        ids_refer_to_tasks_on_any_stack,
+        pop_to_moves_subtasks,
+        move_runs_of_tasks,
+        move_after_a_task,
+        stack_words_count_from_the_top,
+        dup_copies_tags,
+        filter_tasks_by_tag,
+        notes_stay_with_their_task,
+        edit_in_place,
+        time_follows_the_top,
+        drop_stack_deletes_tasks,
+        manage_reminders_by_id_prefix,
+        nearest_binding_applies,
+        rename_and_copy_stacks,
+        merge_stack_keeps_order,
+    );
+
+    fn task_texts<S: Store>(store: &S) -> Vec<String> {
+        list_tasks(store).unwrap().into_iter().map(|task| task.task).collect()
+    }
+
+    fn push_all<S: Store>(store: &mut S, tasks: &[&str]) {
+        for task in tasks {
+            push_task(store, task.to_string()).unwrap();
+        }
+    }
+
+    fn push_goes_on_top<S: Store>(store: &mut S) {
+        push_all(store, &["a", "b", "c"]);
+        assert_eq!(task_texts(store), vec!["a", "b", "c"]);
+        let indices: Vec<Option<TaskIndex>> = list_tasks(store).unwrap().into_iter().map(|task| task.index).collect();
+        assert_eq!(indices, vec![Some(0), Some(1), Some(2)]);
+    }
+
+    fn pushback_goes_on_bottom<S: Store>(store: &mut S) {
+        pushback_task(store, "a".into()).unwrap();
+        push_task(store, "b".into()).unwrap();
+        pushback_task(store, "c".into()).unwrap();
+        push_task(store, "d".into()).unwrap();
+        assert_eq!(task_texts(store), vec!["c", "a", "b", "d"]);
+    }
+
+    fn pop_takes_the_top_task<S: Store>(store: &mut S) {
+        assert!(pop_task(store).unwrap().is_none());
+        push_all(store, &["a", "b"]);
+        pushback_task(store, "c".into()).unwrap();
+        let popped = pop_task(store).unwrap().unwrap();
+        assert_eq!(popped.task, "b");
+        assert_eq!(popped.status, TaskStatus::Done);
+        assert!(popped.finished_at.is_some());
+        assert_eq!(task_texts(store), vec!["c", "a"]);
+        // New tasks still go on top of what's left.
+        push_task(store, "d".into()).unwrap();
+        assert_eq!(task_texts(store), vec!["c", "a", "d"]);
+        let finished: Vec<String> = list_finished_tasks(store, None, None, None).unwrap().into_iter().map(|task| task.task).collect();
+        assert_eq!(finished, vec!["b"]);
+    }
+
+    fn finished_tasks_by_stack_and_time<S: Store>(store: &mut S) {
+        push_all(store, &["a", "b", "c"]);
+        let done = pop_task(store).unwrap().unwrap();
+        let abandoned = kill_task(store, TaskRef::Index(0)).unwrap();
+        assert_eq!((done.task.as_str(), done.status), ("c", TaskStatus::Done));
+        assert_eq!((abandoned.task.as_str(), abandoned.status), ("a", TaskStatus::Abandoned));
+        new_stack(store, "other".into()).unwrap();
+        switch_to_stack(store, "other".into()).unwrap();
+        let other = push_task(store, "d".into()).unwrap();
+        pop_task(store).unwrap();
+        for (id, finished_at) in [(done.id, 100), (abandoned.id, 199), (other.id, 200)] {
+            let mut task = get_task_record(store, id).unwrap();
+            task.finished_at = Some(finished_at);
+            store.update_task(&task).unwrap();
+        }
+
+        let log = |stack_id, since, until| -> Vec<(String, TaskStatus)> {
+            list_finished_tasks(store, stack_id, since, until).unwrap().into_iter().map(|task| (task.task, task.status)).collect()
+        };
+        let (a, c, d) = (("a".into(), TaskStatus::Abandoned), ("c".into(), TaskStatus::Done), ("d".into(), TaskStatus::Done));
+        assert_eq!(log(None, None, None), vec![d.clone(), a.clone(), c.clone()]);
+        assert_eq!(log(Some(DEFAULT_STACK_ID), None, None), vec![a.clone(), c.clone()]);
+        // `since` is included and `until` isn't.
+        assert_eq!(log(None, Some(100), Some(200)), vec![a.clone(), c]);
+        assert_eq!(log(None, Some(101), None), vec![d, a]);
+    }
+
+    fn insert_after_goes_between<S: Store>(store: &mut S) {
+        push_all(store, &["a", "b", "c"]);
+        insert_after(store, TaskRef::Index(0), "x".into()).unwrap();
+        assert_eq!(task_texts(store), vec!["a", "x", "b", "c"]);
+        insert_after(store, TaskRef::Index(2), "y".into()).unwrap();
+        assert_eq!(task_texts(store), vec!["a", "x", "b", "y", "c"]);
+    }
+
+    fn insert_after_top_task<S: Store>(store: &mut S) {
+        push_all(store, &["a", "b"]);
+        insert_after(store, TaskRef::Index(1), "c".into()).unwrap();
+        assert_eq!(task_texts(store), vec!["a", "b", "c"]);
+        assert_eq!(pop_task(store).unwrap().unwrap().task, "c");
+        assert!(matches!(insert_after(store, TaskRef::Index(5), "x".into()), Err(AppError::Task(TaskError::NoSuchTask(TaskRef::Index(5))))));
+    }
+
+    fn repeated_insert_after_keeps_order<S: Store>(store: &mut S) {
+        push_all(store, &["bottom", "top"]);
+        let bottom = TaskRef::Id(list_tasks(store).unwrap()[0].id);
+        let mut expected = vec!["bottom".to_string()];
+        // Many more times than the gap between two orders can be halved.
+        for i in 0..200 {
+            insert_after(store, bottom, i.to_string()).unwrap();
+            expected.insert(1, i.to_string());
+        }
+        expected.push("top".into());
+        assert_eq!(task_texts(store), expected);
+    }
+
+    fn orders<S: Store>(store: &S) -> Vec<f64> {
+        store.open_tasks(get_current_context(store).unwrap()).unwrap().into_iter().map(|task| task.order).collect()
+    }
+
+    fn orders_are_per_stack<S: Store>(store: &mut S) {
+        push_all(store, &["a", "b", "c"]);
+        new_stack(store, "other".into()).unwrap();
+        switch_to_stack(store, "other".into()).unwrap();
+        push_task(store, "x".into()).unwrap();
+        pushback_task(store, "y".into()).unwrap();
+        assert_eq!(orders(store), vec![0., 1.]);
+    }
+
+    fn compact_spreads_orders_out<S: Store>(store: &mut S) {
+        push_all(store, &["a", "b"]);
+        let a = TaskRef::Id(list_tasks(store).unwrap()[0].id);
+        insert_after(store, a, "a1".into()).unwrap();
+        pushback_task(store, "z".into()).unwrap();
+        dive(store).unwrap();
+        push_all(store, &["b1", "b2"]);
+        bury_task(store).unwrap();
+        assert_eq!(orders(store), vec![0., 1.]);
+
+        assert_eq!(compact_orders(store).unwrap(), 6);
+        assert_eq!(orders(store), vec![1., 2.]);
+        assert_eq!(task_texts(store), vec!["b2", "b1"]);
+        surface(store).unwrap();
+        assert_eq!(orders(store), vec![1., 2., 3., 4.]);
+        assert_eq!(task_texts(store), vec!["z", "a", "a1", "b"]);
+        assert_eq!(compact_orders(store).unwrap(), 0);
+    }
+
+    fn swap_exchanges_positions<S: Store>(store: &mut S) {
+        push_all(store, &["a", "b", "c"]);
+        swap_tasks(store, TaskRef::Index(0), TaskRef::Index(2)).unwrap();
+        assert_eq!(task_texts(store), vec!["c", "b", "a"]);
+        swap_tasks(store, TaskRef::Index(1), TaskRef::Index(1)).unwrap();
+        assert_eq!(task_texts(store), vec!["c", "b", "a"]);
+        assert_eq!(pop_task(store).unwrap().unwrap().task, "a");
+    }
+
+    fn swap_rejects_bad_refs<S: Store>(store: &mut S) {
+        push_all(store, &["a"]);
+        assert!(matches!(swap_tasks(store, TaskRef::Index(0), TaskRef::Index(3)), Err(AppError::Task(TaskError::NoSuchTask(TaskRef::Index(3))))));
+        assert!(matches!(swap_tasks(store, TaskRef::Index(2), TaskRef::Index(3)), Err(AppError::Task(TaskError::NoSuchTasks(..)))));
+        assert_eq!(task_texts(store), vec!["a"]);
+    }
+
+    fn ordering_is_per_substack<S: Store>(store: &mut S) {
+        push_all(store, &["a", "b"]);
+        dive(store).unwrap();
+        push_all(store, &["b1", "b2"]);
+        pushback_task(store, "b0".into()).unwrap();
+        assert_eq!(task_texts(store), vec!["b0", "b1", "b2"]);
+        assert!(matches!(swap_tasks(store, TaskRef::Index(0), TaskRef::Id(1)), Err(AppError::Task(TaskError::NotOnSameStack(..)))));
+        surface(store).unwrap();
+        assert_eq!(task_texts(store), vec!["a", "b"]);
+        let tree: Vec<(String, usize)> = list_task_tree(store).unwrap().into_iter().map(|task| (task.task.task, task.depth)).collect();
+        assert_eq!(tree, vec![("a".into(), 0), ("b".into(), 0), ("b0".into(), 1), ("b1".into(), 1), ("b2".into(), 1)]);
+    }
+
+    fn popping_last_subtask_surfaces_parent<S: Store>(store: &mut S) {
+        push_task(store, "parent".into()).unwrap();
+        dive(store).unwrap();
+        push_task(store, "child".into()).unwrap();
+        assert_eq!(list_tasks(store).unwrap()[0].task, "child");
+        assert_eq!(get_current_context_path(store).unwrap(), vec!["parent"]);
+        assert_eq!(surface(store).unwrap().task, "parent");
+        assert!(matches!(pop_task(store), Err(AppError::Task(TaskError::HasOpenSubtasks(_, 1)))));
+        dive(store).unwrap();
+        assert_eq!(pop_task(store).unwrap().unwrap().task, "child");
+        assert_eq!(get_current_context(store).unwrap().parent_id, None);
+        assert_eq!(pop_task(store).unwrap().unwrap().task, "parent");
+    }
+
+    fn kill_abandons_subtasks<S: Store>(store: &mut S) {
+        push_task(store, "parent".into()).unwrap();
+        dive(store).unwrap();
+        push_task(store, "child".into()).unwrap();
+        surface(store).unwrap();
+        kill_task(store, TaskRef::Index(0)).unwrap();
+        let finished = list_finished_tasks(store, None, None, None).unwrap();
+        assert_eq!(finished.len(), 2);
+        assert!(finished.iter().all(|task| task.status == TaskStatus::Abandoned));
+    }
+
+    fn ids_refer_to_tasks_on_any_stack<S: Store>(store: &mut S) {
+        push_all(store, &["a", "b"]);
+        let ids: Vec<TaskRef> = list_tasks(store).unwrap().iter().map(|task| TaskRef::Id(task.id)).collect();
+        new_stack(store, "other".into()).unwrap();
+        switch_to_stack(store, "other".into()).unwrap();
+        push_task(store, "c".into()).unwrap();
+
+        swap_tasks(store, ids[0], ids[1]).unwrap();
+        assert!(matches!(swap_tasks(store, ids[0], TaskRef::Index(0)), Err(AppError::Task(TaskError::NotOnSameStack(..)))));
+        insert_after(store, ids[0], "d".into()).unwrap();
+        assert_eq!(kill_task(store, ids[1]).unwrap().task, "b");
+        switch_to_stack(store, "default".into()).unwrap();
+        assert_eq!(task_texts(store), vec!["a", "d"]);
+        assert!(matches!(kill_task(store, ids[1]), Err(AppError::Task(TaskError::NoSuchTask(_)))));
+    }
+
+    fn pop_to_moves_subtasks<S: Store>(store: &mut S) {
+        new_stack(store, "other".into()).unwrap();
+        push_all(store, &["a", "b"]);
+        dive(store).unwrap();
+        push_task(store, "b1".into()).unwrap();
+        surface(store).unwrap();
+        assert!(matches!(pop_to(store, "nowhere".into()), Err(AppError::Stack(StackError::NoSuchStack(_)))));
+        assert_eq!(pop_to(store, "other".into()).unwrap().unwrap().stack, "other");
+        assert_eq!(task_texts(store), vec!["a"]);
+        switch_to_stack(store, "other".into()).unwrap();
+        assert_eq!(task_texts(store), vec!["b"]);
+        dive(store).unwrap();
+        assert_eq!(task_texts(store), vec!["b1"]);
+    }
+
+    fn range(start: TaskIndex, end: TaskIndex) -> TaskRange {
+        TaskRange { start: TaskRef::Index(start), end: TaskRef::Index(end) }
+    }
+
+    fn move_runs_of_tasks<S: Store>(store: &mut S) {
+        new_stack(store, "other".into()).unwrap();
+        switch_to_stack(store, "other".into()).unwrap();
+        push_all(store, &["x", "y"]);
+        switch_to_stack(store, "default".into()).unwrap();
+        push_all(store, &["a", "b", "c", "d"]);
+        let c = list_tasks(store).unwrap()[2].id;
+        store.insert_reminder(&ReminderRecord { id: "r".into(), task_id: c, due_at: 100, cancelled: false }).unwrap();
+
+        let moved = move_tasks(store, range(2, 1), "other".into(), MovePosition::Bottom).unwrap();
+        let moved: Vec<String> = moved.into_iter().map(|task| task.task).collect();
+        assert_eq!(moved, vec!["b", "c"]);
+        assert_eq!(task_texts(store), vec!["a", "d"]);
+        move_tasks(store, TaskRef::Index(1).into(), "other".into(), MovePosition::Top).unwrap();
+        switch_to_stack(store, "other".into()).unwrap();
+        assert_eq!(task_texts(store), vec!["b", "c", "x", "y", "d"]);
+        let reminders = list_reminders(store).unwrap();
+        assert_eq!((reminders[0].task.as_str(), reminders[0].stack.as_str()), ("c", "other"));
+
+        // Moving within a stack reorders it.
+        move_tasks(store, range(0, 1), "other".into(), MovePosition::Top).unwrap();
+        assert_eq!(task_texts(store), vec!["x", "y", "d", "b", "c"]);
+        assert!(matches!(move_tasks(store, range(0, 9), "default".into(), MovePosition::Top), Err(AppError::Task(TaskError::NoSuchTask(_)))));
+    }
+
+    fn move_after_a_task<S: Store>(store: &mut S) {
+        new_stack(store, "other".into()).unwrap();
+        switch_to_stack(store, "other".into()).unwrap();
+        push_all(store, &["x", "y"]);
+        dive(store).unwrap();
+        let y1 = push_task(store, "y1".into()).unwrap();
+        let y2 = push_task(store, "y2".into()).unwrap();
+        surface(store).unwrap();
+        switch_to_stack(store, "default".into()).unwrap();
+        push_all(store, &["a", "b", "c"]);
+
+        move_tasks(store, range(0, 1), "other".into(), MovePosition::After(TaskRef::Index(0))).unwrap();
+        assert_eq!(task_texts(store), vec!["c"]);
+        move_tasks(store, TaskRef::Index(0).into(), "other".into(), MovePosition::After(TaskRef::Id(y1.id))).unwrap();
+        switch_to_stack(store, "other".into()).unwrap();
+        assert_eq!(task_texts(store), vec!["x", "a", "b", "y"]);
+        let y = TaskRef::Id(list_tasks(store).unwrap()[3].id);
+        dive(store).unwrap();
+        assert_eq!(task_texts(store), vec!["y1", "c", "y2"]);
+        surface(store).unwrap();
+
+        // Tasks can't go inside themselves.
+        assert!(matches!(move_tasks(store, y.into(), "other".into(), MovePosition::After(TaskRef::Id(y2.id))), Err(AppError::Task(TaskError::MoveAfterItself(_)))));
+        assert!(matches!(move_tasks(store, range(0, 1), "other".into(), MovePosition::After(TaskRef::Index(1))), Err(AppError::Task(TaskError::MoveAfterItself(_)))));
+        assert!(matches!(move_tasks(store, range(0, 1), "default".into(), MovePosition::After(TaskRef::Id(y2.id))), Err(AppError::Task(TaskError::NoSuchTask(_)))));
+
+        // Moving the task whose sub-stack we're in takes us with it.
+        dive(store).unwrap();
+        move_tasks(store, y.into(), "default".into(), MovePosition::Top).unwrap();
+        assert_eq!(get_current_stack_name(store).unwrap(), "default");
+        assert_eq!(task_texts(store), vec!["y1", "c", "y2"]);
+    }
+
+    fn stack_words_count_from_the_top<S: Store>(store: &mut S) {
+        assert!(matches!(peek_task(store), Err(AppError::Task(TaskError::NoTasks))));
+        push_all(store, &["a", "b", "c", "d"]);
+        assert_eq!(peek_task(store).unwrap().index, Some(3));
+        assert_eq!(roll_task(store, 2).unwrap().task, "b");
+        assert_eq!(task_texts(store), vec!["a", "c", "d", "b"]);
+        roll_task(store, 0).unwrap();
+        assert_eq!(task_texts(store), vec!["a", "c", "d", "b"]);
+        assert!(matches!(roll_task(store, 4), Err(AppError::Task(TaskError::NotEnoughTasks(5, 4)))));
+        bury_task(store).unwrap();
+        assert_eq!(task_texts(store), vec!["b", "a", "c", "d"]);
+        dup_task(store, 0).unwrap();
+        dup_task(store, 1).unwrap();
+        assert_eq!(task_texts(store), vec!["b", "a", "c", "d", "d", "d"]);
+        roll_task(store, 3).unwrap();
+        dup_task(store, 1).unwrap();
+        assert_eq!(task_texts(store), vec!["b", "a", "d", "d", "d", "c", "d"]);
+        assert_eq!(peek_task(store).unwrap().task, "d");
+    }
+
+    fn dup_copies_tags<S: Store>(store: &mut S) {
+        let task = push_task(store, "fix #ci".into()).unwrap();
+        tag_task(store, TaskRef::Id(task.id), &["urgent".into()]).unwrap();
+        push_task(store, "b".into()).unwrap();
+        let copy = dup_task(store, 1).unwrap();
+        assert_ne!(copy.id, task.id);
+        assert_eq!((copy.task.as_str(), copy.tags), ("fix #ci", vec!["ci".to_string(), "urgent".to_string()]));
+        assert_eq!(dup_task(store, 0).unwrap().tags, vec!["ci", "urgent"]);
+    }
+
+    fn filter_tasks_by_tag<S: Store>(store: &mut S) {
+        let a = push_task(store, "fix #ci for #12".into()).unwrap();
+        assert_eq!(a.tags, vec!["ci"]);
+        let b = push_task(store, "b".into()).unwrap();
+        assert_eq!(tag_task(store, TaskRef::Id(b.id), &["#urgent".into(), "ci".into()]).unwrap().tags, vec!["ci", "urgent"]);
+        assert!(matches!(tag_task(store, TaskRef::Id(b.id), &["not valid".into()]), Err(AppError::Task(TaskError::InvalidTag(_)))));
+        push_task(store, "c".into()).unwrap();
+        new_stack(store, "other".into()).unwrap();
+        switch_to_stack(store, "other".into()).unwrap();
+        push_task(store, "d #ci".into()).unwrap();
+        dive(store).unwrap();
+        push_task(store, "d1 #ci #urgent".into()).unwrap();
+
+        let texts = |tasks: Vec<Task>| tasks.into_iter().map(|task| task.task).collect::<Vec<_>>();
+        let ci = TagFilter { tags: vec!["ci".into()], not_tags: Vec::new() };
+        assert_eq!(texts(find_tasks(store, &ci).unwrap()), vec!["fix #ci for #12", "b", "d #ci", "d1 #ci #urgent"]);
+        let not_urgent = TagFilter { not_tags: vec!["urgent".into()], ..ci.clone() };
+        assert_eq!(texts(find_tasks(store, &not_urgent).unwrap()), vec!["fix #ci for #12", "d #ci"]);
+        let both = TagFilter { tags: vec!["ci".into(), "urgent".into()], not_tags: Vec::new() };
+        assert_eq!(texts(find_tasks(store, &both).unwrap()), vec!["b", "d1 #ci #urgent"]);
+
+        let stacks = list_stacks(store).unwrap();
+        assert_eq!(stacks[0].tags, BTreeMap::from([("ci".into(), 2), ("urgent".into(), 1)]));
+        assert_eq!(stacks[1].tags, BTreeMap::from([("ci".into(), 2), ("urgent".into(), 1)]));
+        // Finished tasks don't count.
+        pop_task(store).unwrap();
+        assert_eq!(list_stacks(store).unwrap()[1].tags, BTreeMap::from([("ci".into(), 1)]));
+    }
+
+    fn notes_stay_with_their_task<S: Store>(store: &mut S) {
+        let a = push_task(store, "a".into()).unwrap();
+        push_task(store, "b".into()).unwrap();
+        add_note(store, TaskRef::Index(1), "  see the build log\n".into()).unwrap();
+        add_note(store, TaskRef::Index(1), "https://example.com/pr/1".into()).unwrap();
+        add_note(store, TaskRef::Id(a.id), "unrelated".into()).unwrap();
+        assert!(matches!(add_note(store, TaskRef::Index(0), " \n".into()), Err(AppError::Task(TaskError::EmptyNote))));
+
+        new_stack(store, "other".into()).unwrap();
+        let b = pop_to(store, "other".into()).unwrap().unwrap();
+        let details = show_task(store, TaskRef::Id(b.id)).unwrap();
+        assert_eq!(details.task.stack, "other");
+        assert_eq!(details.task.index, Some(0));
+        let notes: Vec<String> = details.notes.into_iter().map(|note| note.note).collect();
+        assert_eq!(notes, vec!["see the build log", "https://example.com/pr/1"]);
+
+        // Finished tasks keep their notes, and can still be shown by ID.
+        kill_task(store, TaskRef::Id(a.id)).unwrap();
+        let details = show_task(store, TaskRef::Id(a.id)).unwrap();
+        assert_eq!(details.task.status, TaskStatus::Abandoned);
+        assert_eq!(details.notes.len(), 1);
+        assert!(matches!(show_task(store, TaskRef::Index(0)), Err(AppError::Task(TaskError::NoSuchTask(_)))));
+    }
+
+    fn edit_in_place<S: Store>(store: &mut S) {
+        push_task(store, "bottom".into()).unwrap();
+        let task = push_task(store, "fix teh build #ci".into()).unwrap();
+        push_task(store, "top".into()).unwrap();
+        store.insert_reminder(&ReminderRecord { id: "r".into(), task_id: task.id, due_at: 100, cancelled: false }).unwrap();
+        tag_task(store, TaskRef::Id(task.id), &["urgent".into()]).unwrap();
+
+        let edited = edit_task(store, TaskRef::Index(1), "fix the build #release".into()).unwrap();
+        assert_eq!(edited.id, task.id);
+        assert_eq!(edited.reminders, 1);
+        // Tags written in the old text go with it; others stay.
+        assert_eq!(edited.tags, vec!["release", "urgent"]);
+        assert_eq!(task_texts(store), vec!["bottom", "fix the build #release", "top"]);
+        assert!(matches!(edit_task(store, TaskRef::Index(1), " ".into()), Err(AppError::Task(TaskError::EmptyTask))));
+
+        dive(store).unwrap();
+        push_task(store, "the sub-task".into()).unwrap();
+        let substitution: Substitution = "s/the/a/".parse().unwrap();
+        let edited = substitute_tasks(store, DEFAULT_STACK_ID, &substitution).unwrap();
+        let mut texts: Vec<String> = edited.into_iter().map(|task| task.task).collect();
+        texts.sort();
+        assert_eq!(texts, vec!["a sub-task", "fix a build #release"]);
+        assert!(substitute_tasks(store, DEFAULT_STACK_ID, &"s/nothing/else/".parse().unwrap()).unwrap().is_empty());
+        assert!(matches!(substitute_tasks(store, DEFAULT_STACK_ID, &"s/.*//".parse().unwrap()), Err(AppError::Task(TaskError::EmptyTask))));
+        assert_eq!(task_texts(store), vec!["a sub-task"]);
+    }
+
+    fn time_follows_the_top<S: Store>(store: &mut S) {
+        let total = |name: &str, task_id: Option<TaskId>, seconds: i64| TimeTotal { name: name.into(), task_id, seconds };
+        let a = push_task(store, "a".into()).unwrap();
+        track_time(store, 100).unwrap();
+        let b = push_task(store, "b".into()).unwrap();
+        track_time(store, 160).unwrap();
+        track_time(store, 170).unwrap();
+        assert_eq!(pause_timer(store, 200).unwrap().unwrap().id, b.id);
+        assert!(matches!(pause_timer(store, 210), Err(AppError::Time(TimeError::AlreadyPaused))));
+        let c = push_task(store, "c".into()).unwrap();
+        track_time(store, 250).unwrap();
+        assert_eq!(resume_timer(store, 300).unwrap().unwrap().id, c.id);
+        assert!(matches!(resume_timer(store, 310), Err(AppError::Time(TimeError::NotPaused))));
+        pop_task(store).unwrap();
+        track_time(store, 400).unwrap();
+        // An empty sub-stack leaves the task owning it on the clock.
+        dive(store).unwrap();
+        track_time(store, 420).unwrap();
+
+        assert_eq!(time_totals(store, TimeGrouping::Task, None, None, 460).unwrap(),
+            vec![total("b", Some(b.id), 100), total("c", Some(c.id), 100), total("a", Some(a.id), 60)]);
+        assert_eq!(time_totals(store, TimeGrouping::Task, Some(150), Some(350), 460).unwrap(),
+            vec![total("c", Some(c.id), 50), total("b", Some(b.id), 40), total("a", Some(a.id), 10)]);
+
+        surface(store).unwrap();
+        new_stack(store, "other".into()).unwrap();
+        switch_to_stack(store, "other".into()).unwrap();
+        track_time(store, 500).unwrap();
+        push_task(store, "d".into()).unwrap();
+        track_time(store, 540).unwrap();
+        assert_eq!(time_totals(store, TimeGrouping::Stack, None, None, 600).unwrap(), vec![total("default", None, 300), total("other", None, 60)]);
+        let day = Local.timestamp_opt(100, 0).unwrap().format("%Y-%m-%d").to_string();
+        assert_eq!(time_totals(store, TimeGrouping::Day, None, None, 600).unwrap(), vec![total(&day, None, 360)]);
+    }
+
+    fn drop_stack_deletes_tasks<S: Store>(store: &mut S) {
+        new_stack(store, "other".into()).unwrap();
+        assert!(matches!(new_stack(store, "other".into()), Err(AppError::Stack(StackError::StackAlreadyExists(_)))));
+        switch_to_stack(store, "other".into()).unwrap();
+        push_task(store, "a".into()).unwrap();
+        assert!(matches!(drop_stack(store, "other".into()), Err(AppError::Stack(StackError::CantDeleteCurrentStack))));
+        switch_to_stack(store, "default".into()).unwrap();
+        assert!(matches!(drop_stack(store, "default".into()), Err(AppError::Stack(StackError::CantDeleteDefaultStack))));
+        drop_stack(store, "other".into()).unwrap();
+        let stacks: Vec<String> = list_stacks(store).unwrap().into_iter().map(|stack| stack.name).collect();
+        assert_eq!(stacks, vec!["default"]);
+        assert!(list_finished_tasks(store, None, None, None).unwrap().is_empty());
+    }
+
+    fn rename_and_copy_stacks<S: Store>(store: &mut S) {
+        new_stack(store, "work".into()).unwrap();
+        assert!(matches!(rename_stack(store, "work", "default".into()), Err(AppError::Stack(StackError::StackAlreadyExists(_)))));
+        rename_stack(store, "work", "job".into()).unwrap();
+        switch_to_stack(store, "job".into()).unwrap();
+        push_all(store, &["a", "b"]);
+        dive(store).unwrap();
+        push_task(store, "b1".into()).unwrap();
+        surface(store).unwrap();
+        let task = push_task(store, "c".into()).unwrap();
+        store.insert_reminder(&ReminderRecord { id: "r".into(), task_id: task.id, due_at: 100, cancelled: false }).unwrap();
+        pop_task(store).unwrap();
+
+        let (copy, reminder_ids) = copy_stack(store, "job", "copy".into()).unwrap();
+        assert_eq!(copy.name, "copy");
+        assert!(reminder_ids.is_empty());
+        assert!(matches!(copy_stack(store, "job", "default".into()), Err(AppError::Stack(StackError::StackAlreadyExists(_)))));
+        switch_to_stack(store, "copy".into()).unwrap();
+        assert_eq!(task_texts(store), vec!["a", "b"]);
+        dive(store).unwrap();
+        assert_eq!(task_texts(store), vec!["b1"]);
+        assert!(list_finished_tasks(store, Some(stack_name_to_id(store, "copy").unwrap()), None, None).unwrap().is_empty());
+
+        switch_to_stack(store, "job".into()).unwrap();
+        let task = push_task(store, "d".into()).unwrap();
+        store.insert_reminder(&ReminderRecord { id: "s".into(), task_id: task.id, due_at: 200, cancelled: false }).unwrap();
+        let (_, reminder_ids) = copy_stack(store, "job", "again".into()).unwrap();
+        assert_eq!(reminder_ids.len(), 1);
+        let reminders = list_reminders(store).unwrap();
+        assert_eq!(reminders.iter().filter(|reminder| reminder.task == "d" && reminder.due_at == 200).count(), 2);
+    }
+
+    fn merge_stack_keeps_order<S: Store>(store: &mut S) {
+        new_stack(store, "other".into()).unwrap();
+        push_all(store, &["a", "b"]);
+        bind_stack(store, "other".into(), Path::new("/src")).unwrap();
+        switch_to_stack(store, "other".into()).unwrap();
+        push_all(store, &["c", "d"]);
+        dive(store).unwrap();
+        push_task(store, "d1".into()).unwrap();
+        surface(store).unwrap();
+        assert!(matches!(merge_stack(store, "other", "other", MergePosition::OnTop), Err(AppError::Stack(StackError::MergeIntoItself(_)))));
+
+        merge_stack(store, "other", "default", MergePosition::OnTop).unwrap();
+        assert!(store.stack_by_name("other").unwrap().is_none());
+        assert_eq!(get_current_stack_name(store).unwrap(), "default");
+        assert_eq!(task_texts(store), vec!["a", "b", "c", "d"]);
+        assert_eq!(find_binding(store, Path::new("/src")).unwrap().unwrap().stack, "default");
+        dive(store).unwrap();
+        assert_eq!(task_texts(store), vec!["d1"]);
+        surface(store).unwrap();
+
+        new_stack(store, "below".into()).unwrap();
+        switch_to_stack(store, "below".into()).unwrap();
+        push_all(store, &["x", "y"]);
+        pop_task(store).unwrap();
+        merge_stack(store, "below", "default", MergePosition::Below).unwrap();
+        assert_eq!(task_texts(store), vec!["x", "a", "b", "c", "d"]);
+        assert_eq!(list_finished_tasks(store, Some(DEFAULT_STACK_ID), None, None).unwrap()[0].task, "y");
+
+        // The default stack can't be dropped, so it's emptied.
+        new_stack(store, "last".into()).unwrap();
+        merge_stack(store, "default", "last", MergePosition::OnTop).unwrap();
+        assert_eq!(get_current_stack_name(store).unwrap(), "last");
+        assert_eq!(task_texts(store).len(), 5);
+        switch_to_stack(store, "default".into()).unwrap();
+        assert!(task_texts(store).is_empty());
+    }
+
+    fn manage_reminders_by_id_prefix<S: Store>(store: &mut S) {
+        let task = push_task(store, "a".into()).unwrap();
+        for (id, due_at) in [("abc1", 100), ("abc2", 200)] {
+            store.insert_reminder(&ReminderRecord { id: id.into(), task_id: task.id, due_at, cancelled: false }).unwrap();
+        }
+        assert!(matches!(cancel_reminder(store, "abc"), Err(AppError::Reminder(ReminderError::AmbiguousReminder(_)))));
+        assert!(matches!(cancel_reminder(store, "x"), Err(AppError::Reminder(ReminderError::NoSuchReminder(_)))));
+        cancel_reminder(store, "abc1").unwrap();
+        let reminders = list_reminders(store).unwrap();
+        assert_eq!(reminders.len(), 1);
+        assert_eq!(reminders[0].id, "abc2");
+        assert_eq!(list_tasks(store).unwrap()[0].reminders, 1);
+        // A cancelled reminder doesn't fire, but is cleaned up when it comes due.
+        assert_eq!(claim_reminder(store, "abc1").unwrap(), None);
+        assert_eq!(next_reminder(store).unwrap(), Some(("abc2".into(), 200)));
+    }
+
+    #[test]
+    fn waiting_deletes_cancelled_reminders() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("yakstack.db");
+        let mut store = SqliteStore::open(&path).unwrap();
+        crate::migrations::migrate(store.connection_mut()).unwrap();
+        let task = push_task(&mut store, "a".into()).unwrap();
+        store.insert_reminder(&ReminderRecord { id: "r".into(), task_id: task.id, due_at: 100, cancelled: true }).unwrap();
+        trigger_reminder(path.clone(), store, "r".into()).unwrap();
+        assert_eq!(SqliteStore::open(&path).unwrap().reminder("r").unwrap(), None);
+    }
+
+    fn nearest_binding_applies<S: Store>(store: &mut S) {
+        new_stack(store, "repo".into()).unwrap();
+        new_stack(store, "docs".into()).unwrap();
+        bind_stack(store, "repo".into(), Path::new("/src/repo")).unwrap();
+        bind_stack(store, "docs".into(), Path::new("/src/repo/docs")).unwrap();
+        assert!(matches!(bind_stack(store, "nope".into(), Path::new("/")), Err(AppError::Stack(StackError::NoSuchStack(_)))));
+
+        assert_eq!(use_binding(store, Path::new("/src")).unwrap(), None);
+        assert_eq!(get_current_stack_name(store).unwrap(), "default");
+        assert_eq!(use_binding(store, Path::new("/src/repo/docs/api")).unwrap().unwrap().stack, "docs");
+        assert_eq!(get_current_stack_name(store).unwrap(), "docs");
+        // Paths are compared by component, not as strings.
+        assert_eq!(find_binding(store, Path::new("/src/repository")).unwrap(), None);
+
+        // Staying inside the bound stack keeps its sub-stack current.
+        push_task(store, "a".into()).unwrap();
+        dive(store).unwrap();
+        use_binding(store, Path::new("/src/repo/docs")).unwrap();
+        assert!(get_current_context(store).unwrap().parent_id.is_some());
+
+        assert_eq!(unbind(store, Path::new("/src/repo/docs/api")).unwrap().stack, "docs");
+        assert_eq!(find_binding(store, Path::new("/src/repo/docs")).unwrap().unwrap().stack, "repo");
+        switch_to_stack(store, "default".into()).unwrap();
+        drop_stack(store, "repo".into()).unwrap();
+        assert!(matches!(unbind(store, Path::new("/src/repo")), Err(AppError::Stack(StackError::NotBound(_)))));
+    }
+
+    #[test]
+    fn time_is_split_at_local_midnight() {
+        let march = timespec::start_of_day(NaiveDate::from_ymd_opt(2026, 3, 1).unwrap());
+        assert_eq!(split_by_day(march - 60, march + 30), vec![("2026-02-28".into(), 60), ("2026-03-01".into(), 30)]);
+        assert_eq!(split_by_day(march, march + 86400 * 2), vec![("2026-03-01".into(), 86400), ("2026-03-02".into(), 86400)]);
+        assert!(split_by_day(march, march).is_empty());
+    }
+
+    mod ordering {
+        use super::*;
+        use crate::store::MemoryStore;
+
+        use proptest::prelude::*;
+
+        #[derive(Debug, Clone)]
+        enum Op {
+            Push,
+            PushBack,
+            /// Insert after the task at this index, wrapped around, this many times.
+            InsertAfter(usize, usize),
+            Swap(usize, usize),
+            Roll(usize),
+            Bury,
+            Pop,
+            MoveToBottom(usize, usize),
+            Compact,
+        }
+
+        fn op() -> impl Strategy<Value = Op> {
+            prop_oneof![
+                Just(Op::Push),
+                Just(Op::PushBack),
+                (any::<usize>(), 1..40usize).prop_map(|(index, times)| Op::InsertAfter(index, times)),
+                (any::<usize>(), any::<usize>()).prop_map(|(a, b)| Op::Swap(a, b)),
+                any::<usize>().prop_map(Op::Roll),
+                Just(Op::Bury),
+                Just(Op::Pop),
+                (any::<usize>(), any::<usize>()).prop_map(|(a, b)| Op::MoveToBottom(a, b)),
+                Just(Op::Compact),
+            ]
+        }
+
+        proptest! {
+            // Each case does a lot of inserting.
+            #![proptest_config(ProptestConfig::with_cases(64))]
+
+            /// Every way of reordering tasks does to the stack what it does to a list.
+            #[test]
+            fn reordering_matches_a_list(ops in prop::collection::vec(op(), 1..40)) {
+                let mut store = MemoryStore::new();
+                let mut expected: Vec<String> = Vec::new();
+                let mut next_task = 0;
+                let mut new_task = || {
+                    next_task += 1;
+                    next_task.to_string()
+                };
+                for op in ops {
+                    let len = expected.len();
+                    match op {
+                        Op::Push => {
+                            let task = new_task();
+                            push_task(&mut store, task.clone()).unwrap();
+                            expected.push(task);
+                        }
+                        Op::PushBack => {
+                            let task = new_task();
+                            pushback_task(&mut store, task.clone()).unwrap();
+                            expected.insert(0, task);
+                        }
+                        Op::InsertAfter(index, times) if len > 0 => {
+                            let after = TaskRef::Id(list_tasks(&store).unwrap()[index % len].id);
+                            for _ in 0..times {
+                                let task = new_task();
+                                insert_after(&mut store, after, task.clone()).unwrap();
+                                expected.insert(index % len + 1, task);
+                            }
+                        }
+                        Op::Swap(a, b) if len > 0 => {
+                            swap_tasks(&mut store, TaskRef::Index((a % len) as TaskIndex), TaskRef::Index((b % len) as TaskIndex)).unwrap();
+                            expected.swap(a % len, b % len);
+                        }
+                        Op::Roll(depth) if len > 0 => {
+                            roll_task(&mut store, (depth % len) as TaskIndex).unwrap();
+                            let task = expected.remove(len - 1 - depth % len);
+                            expected.push(task);
+                        }
+                        Op::Bury if len > 0 => {
+                            bury_task(&mut store).unwrap();
+                            let task = expected.pop().unwrap();
+                            expected.insert(0, task);
+                        }
+                        Op::Pop if len > 0 => {
+                            pop_task(&mut store).unwrap();
+                            expected.pop();
+                        }
+                        Op::MoveToBottom(a, b) if len > 0 => {
+                            let (start, end) = (a % len, b % len);
+                            let range = TaskRange { start: TaskRef::Index(start as TaskIndex), end: TaskRef::Index(end as TaskIndex) };
+                            move_tasks(&mut store, range, "default".into(), MovePosition::Bottom).unwrap();
+                            let moved: Vec<String> = expected.drain(start.min(end)..=start.max(end)).collect();
+                            expected.splice(0..0, moved);
+                        }
+                        Op::Compact => {
+                            compact_orders(&mut store).unwrap();
+                        }
+                        _ => {}
+                    }
+                    prop_assert_eq!(task_texts(&store), expected.clone());
+                }
+            }
+
+            /// Renormalizing keeps tasks in order, even ones that share an order.
+            #[test]
+            fn renormalizing_keeps_order(task_orders in prop::collection::vec(-3..3i32, 0..30)) {
+                let mut store = MemoryStore::new();
+                for (i, order) in task_orders.iter().enumerate() {
+                    insert_task(&mut store, i.to_string(), *order as f64 / 4., Context { stack_id: DEFAULT_STACK_ID, parent_id: None }).unwrap();
+                }
+                let before = task_texts(&store);
+                renormalize(&mut store, Context { stack_id: DEFAULT_STACK_ID, parent_id: None }).unwrap();
+                prop_assert_eq!(task_texts(&store), before);
+                let expected: Vec<f64> = (1..=task_orders.len()).map(|i| i as f64).collect();
+                prop_assert_eq!(orders(&store), expected);
+            }
+        }
+    }
+}
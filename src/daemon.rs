@@ -0,0 +1,137 @@
+//! Reminder daemon.
+//!
+//! `yakstack daemon` fires every reminder in a database: it sleeps until the
+//! next one is due, and on startup fires the ones that came due while it wasn't
+//! running. It listens on a Unix socket next to the database, `<db>.sock`, so
+//! `remindme` can tell it to look for new reminders; any line written to the
+//! socket does that.
+//!
+//! Reminders are fired through [`claim_reminder`], so the daemon and any
+//! `triggerreminder` processes left over from before it started never fire
+//! the same reminder twice.
+
+use crate::commands::*;
+use crate::errors::*;
+use crate::store::{Store, SqliteStore};
+use crate::types::*;
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use chrono::Utc;
+
+/// Longest time to sleep between looking for due reminders, so that reminders
+/// added without telling the daemon, e.g. by `redo`, still fire.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// Time to wait before looking for due reminders again after failing to.
+const RETRY_SLEEP: Duration = Duration::from_secs(5);
+
+/// Get the socket the daemon for the database at `db_path` listens on.
+pub fn socket_path(db_path: &Path) -> PathBuf {
+    let mut path = db_path.to_path_buf().into_os_string();
+    path.push(".sock");
+    path.into()
+}
+
+/// Tell the daemon for the database at `db_path` to look for new reminders.
+///
+/// Returns whether a daemon is listening.
+pub fn wake(db_path: &Path) -> bool {
+    match UnixStream::connect(socket_path(db_path)) {
+        Ok(mut stream) => stream.write_all(b"wake\n").is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Fire reminders as they come due, forever.
+pub fn run(db_path: &Path, mut store: SqliteStore) -> AppResult<()> {
+    let listener = bind(&socket_path(db_path))?;
+    let (wake_tx, wake_rx) = mpsc::channel();
+    thread::spawn(move || listen(listener, wake_tx));
+
+    loop {
+        let timeout = match fire_due_reminders(&mut store) {
+            Ok(Some(due_at)) => Duration::from_secs((due_at - Utc::now().timestamp()).max(0) as u64).min(MAX_SLEEP),
+            Ok(None) => MAX_SLEEP,
+            // The database may be busy or briefly unreadable; try again on the next tick.
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                RETRY_SLEEP
+            }
+        };
+        match wake_rx.recv_timeout(timeout) {
+            Ok(()) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Err(AppError::Environment("reminder daemon stopped listening".into())),
+        }
+    }
+}
+
+/// Fire every reminder that is due, returning when the next one will be.
+fn fire_due_reminders<S: Store>(store: &mut S) -> AppResult<Option<Timestamp>> {
+    loop {
+        match next_reminder(store)? {
+            Some((reminder_id, due_at)) if due_at <= Utc::now().timestamp() => {
+                if let Some(task) = claim_reminder(store, &reminder_id)? {
+                    // One failed notification shouldn't stop the rest.
+                    if let Err(e) = show_reminder(&task) {
+                        eprintln!("Error: {}", e);
+                    }
+                }
+            }
+            next => return Ok(next.map(|(_, due_at)| due_at)),
+        }
+    }
+}
+
+/// Listen on `path`, unless another daemon already is.
+fn bind(path: &Path) -> AppResult<UnixListener> {
+    if UnixStream::connect(path).is_ok() {
+        return Err(ReminderError::DaemonAlreadyRunning(path.display().to_string()).into());
+    }
+    // Nobody is listening, so this was left behind by a daemon that was killed.
+    if path.exists() {
+        fs::remove_file(path)
+            .map_err(|e| AppError::Environment(format!("unable to remove stale socket {}: {}", path.display(), e)))?;
+    }
+    UnixListener::bind(path)
+        .map_err(|e| AppError::Environment(format!("unable to listen on {}: {}", path.display(), e)))
+}
+
+/// Send a message to `wake_tx` for every line received on `listener`.
+fn listen(listener: UnixListener, wake_tx: Sender<()>) {
+    for stream in listener.incoming().flatten() {
+        for _ in BufReader::new(stream).lines().map_while(Result::ok) {
+            if wake_tx.send(()).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::*;
+
+    #[test]
+    fn missed_reminders_fire_once() {
+        let mut store = MemoryStore::new();
+        let task = push_task(&mut store, "a".into()).unwrap();
+        for (id, due_at) in [("missed", 0), ("later", 9999999999)] {
+            store.insert_reminder(&ReminderRecord { id: id.into(), task_id: task.id, due_at, cancelled: false }).unwrap();
+        }
+        assert_eq!(claim_reminder(&mut store, "missed").unwrap().as_deref(), Some("a"));
+        assert_eq!(claim_reminder(&mut store, "missed").unwrap(), None);
+        assert_eq!(fire_due_reminders(&mut store).unwrap(), Some(9999999999));
+        kill_task(&mut store, TaskRef::Index(0)).unwrap();
+        store.update_reminder(&ReminderRecord { id: "later".into(), task_id: task.id, due_at: 0, cancelled: false }).unwrap();
+        // The task is finished, so this doesn't show a notification.
+        assert_eq!(fire_due_reminders(&mut store).unwrap(), None);
+    }
+}
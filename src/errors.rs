@@ -14,18 +14,45 @@ pub enum StackError {
     #[error("can't delete default stack")]
     CantDeleteDefaultStack,
     #[error("can't delete current stack")]
-    CantDeleteCurrentStack
+    CantDeleteCurrentStack,
+    #[error("no stack is bound to {0} or any directory containing it")]
+    NotBound(String),
+    #[error("can't merge stack '{0}' into itself")]
+    MergeIntoItself(String)
 }
 
 /// Errors related to task management.
 #[derive(Error, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum TaskError {
     #[error("no tasks!")]
     NoTasks,
-    #[error("task #{0} doesn't exist")]
-    NoSuchTask(TaskIndex),
-    #[error("tasks #{0} and #{1} don't exist")]
-    NoSuchTasks(TaskIndex, TaskIndex)
+    #[error("task {0} doesn't exist")]
+    NoSuchTask(TaskRef),
+    #[error("tasks {0} and {1} don't exist")]
+    NoSuchTasks(TaskRef, TaskRef),
+    #[error("'{0}' is not a task index or ID (like '#a3f')")]
+    InvalidTaskRef(String),
+    #[error("tasks {0} and {1} are not on the same stack")]
+    NotOnSameStack(TaskRef, TaskRef),
+    #[error("'{0}' still has {1} open sub-task(s); dive in and finish them first")]
+    HasOpenSubtasks(String, u64),
+    #[error("not inside a sub-stack")]
+    NotInSubstack,
+    #[error("can't move tasks after {0}, which is one of them or inside one of them")]
+    MoveAfterItself(TaskRef),
+    #[error("the stack only has {1} task(s), but {0} are needed")]
+    NotEnoughTasks(TaskIndex, TaskIndex),
+    #[error("'{0}' is not a valid tag; tags are made of letters, digits, '_' and '-'")]
+    InvalidTag(String),
+    #[error("notes can't be empty")]
+    EmptyNote,
+    #[error("tasks can't be empty")]
+    EmptyTask,
+    #[error("'{0}' is not a substitution like 's/old/new/'")]
+    InvalidSubstitution(String),
+    #[error("invalid regex: {0}")]
+    InvalidRegex(String)
 }
 
 #[derive(Error, Debug)]
@@ -33,13 +60,73 @@ pub enum CommandError {
     #[error("could not find command matching '{0}'")]
     NoMatchingCommand(String),
     #[error("more than one command matches '{0}'")]
-    AmbiguousPrefix(String)
+    AmbiguousPrefix(String),
+    #[error("{1} can't use the {0} format")]
+    UnsupportedFormat(String, String)
 }
 
 #[derive(Error, Debug)]
 pub enum ReminderError {
     #[error("reminder time '{0}' is not valid")]
-    InvalidReminderTime(String)
+    InvalidReminderTime(String),
+    #[error("reminder time '{0}' is in the past")]
+    TimeInPast(String),
+    #[error("a reminder daemon is already listening on {0}")]
+    DaemonAlreadyRunning(String),
+    #[error("no pending reminder matches '{0}'")]
+    NoSuchReminder(String),
+    #[error("more than one reminder matches '{0}'")]
+    AmbiguousReminder(String)
+}
+
+/// Errors related to undo and redo.
+#[derive(Error, Debug)]
+pub enum JournalError {
+    #[error("nothing to undo")]
+    NothingToUndo,
+    #[error("nothing to redo")]
+    NothingToRedo
+}
+
+/// Errors related to schema migrations.
+#[derive(Error, Debug)]
+pub enum MigrationError {
+    #[error("database schema version {found} is newer than this yakstack supports (version {supported})")]
+    SchemaTooNew { found: u32, supported: u32 },
+    #[error("migration to schema version {version} failed: {reason}")]
+    Failed { version: u32, reason: String }
+}
+
+/// Errors in documents being imported.
+#[derive(Error, Debug)]
+pub enum ImportError {
+    #[error("not a valid yakstack document: {0}")]
+    InvalidDocument(String),
+    #[error("document version {found} is not supported (expected version {supported})")]
+    UnsupportedVersion { found: u32, supported: u32 },
+    #[error("document has a stack with no name")]
+    EmptyStackName,
+    #[error("stack '{0}' appears more than once in the document")]
+    DuplicateStack(String),
+    #[error("document has a task with no text on stack '{0}'")]
+    EmptyTask(String),
+    #[error("history of stack '{0}' has unfinished task '{1}'")]
+    UnfinishedHistoryTask(String, String),
+    #[error("document's current stack '{0}' is not in the document")]
+    NoSuchCurrentStack(String),
+    #[error("document has invalid tag '{1}' on stack '{0}'")]
+    InvalidTag(String, String),
+    #[error("document has an empty note on task '{1}' of stack '{0}'")]
+    EmptyNote(String, String)
+}
+
+/// Errors related to time tracking.
+#[derive(Error, Debug)]
+pub enum TimeError {
+    #[error("time tracking is already paused")]
+    AlreadyPaused,
+    #[error("time tracking isn't paused")]
+    NotPaused
 }
 
 #[derive(Error, Debug)]
@@ -55,7 +142,68 @@ pub enum AppError {
     #[error("{0}")]
     Environment(String),
     #[error("{0}")]
-    Reminder(#[from] ReminderError)
+    Reminder(#[from] ReminderError),
+    #[error("{0}")]
+    Migration(#[from] MigrationError),
+    #[error("{0}")]
+    Journal(#[from] JournalError),
+    #[error("{0}")]
+    Import(#[from] ImportError),
+    #[error("{0}")]
+    Time(#[from] TimeError)
+}
+
+impl AppError {
+    /// Get a stable, machine-readable identifier for the error, used in JSON output.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Stack(StackError::NoSuchStack(_)) => "no_such_stack",
+            AppError::Stack(StackError::StackAlreadyExists(_)) => "stack_already_exists",
+            AppError::Stack(StackError::CantDeleteDefaultStack) => "cant_delete_default_stack",
+            AppError::Stack(StackError::CantDeleteCurrentStack) => "cant_delete_current_stack",
+            AppError::Stack(StackError::NotBound(_)) => "not_bound",
+            AppError::Stack(StackError::MergeIntoItself(_)) => "merge_into_itself",
+            AppError::Task(TaskError::NoTasks) => "no_tasks",
+            AppError::Task(TaskError::NoSuchTask(_)) => "no_such_task",
+            AppError::Task(TaskError::NoSuchTasks(_, _)) => "no_such_tasks",
+            AppError::Task(TaskError::InvalidTaskRef(_)) => "invalid_task_ref",
+            AppError::Task(TaskError::NotOnSameStack(_, _)) => "not_on_same_stack",
+            AppError::Task(TaskError::HasOpenSubtasks(_, _)) => "has_open_subtasks",
+            AppError::Task(TaskError::NotInSubstack) => "not_in_substack",
+            AppError::Task(TaskError::MoveAfterItself(_)) => "move_after_itself",
+            AppError::Task(TaskError::NotEnoughTasks(_, _)) => "not_enough_tasks",
+            AppError::Task(TaskError::InvalidTag(_)) => "invalid_tag",
+            AppError::Task(TaskError::EmptyNote) => "empty_note",
+            AppError::Task(TaskError::EmptyTask) => "empty_task_text",
+            AppError::Task(TaskError::InvalidSubstitution(_)) => "invalid_substitution",
+            AppError::Task(TaskError::InvalidRegex(_)) => "invalid_regex",
+            AppError::Sqlite(_) => "database_error",
+            AppError::Command(CommandError::NoMatchingCommand(_)) => "no_matching_command",
+            AppError::Command(CommandError::AmbiguousPrefix(_)) => "ambiguous_prefix",
+            AppError::Command(CommandError::UnsupportedFormat(_, _)) => "unsupported_format",
+            AppError::Environment(_) => "environment_error",
+            AppError::Reminder(ReminderError::InvalidReminderTime(_)) => "invalid_reminder_time",
+            AppError::Reminder(ReminderError::TimeInPast(_)) => "reminder_time_in_past",
+            AppError::Reminder(ReminderError::DaemonAlreadyRunning(_)) => "daemon_already_running",
+            AppError::Reminder(ReminderError::NoSuchReminder(_)) => "no_such_reminder",
+            AppError::Reminder(ReminderError::AmbiguousReminder(_)) => "ambiguous_reminder",
+            AppError::Migration(MigrationError::SchemaTooNew { .. }) => "schema_too_new",
+            AppError::Migration(MigrationError::Failed { .. }) => "migration_failed",
+            AppError::Journal(JournalError::NothingToUndo) => "nothing_to_undo",
+            AppError::Journal(JournalError::NothingToRedo) => "nothing_to_redo",
+            AppError::Import(ImportError::InvalidDocument(_)) => "invalid_document",
+            AppError::Import(ImportError::UnsupportedVersion { .. }) => "unsupported_document_version",
+            AppError::Import(ImportError::EmptyStackName) => "empty_stack_name",
+            AppError::Import(ImportError::DuplicateStack(_)) => "duplicate_stack",
+            AppError::Import(ImportError::EmptyTask(_)) => "empty_task",
+            AppError::Import(ImportError::UnfinishedHistoryTask(_, _)) => "unfinished_history_task",
+            AppError::Import(ImportError::NoSuchCurrentStack(_)) => "no_such_current_stack",
+            AppError::Import(ImportError::InvalidTag(_, _)) => "invalid_document_tag",
+            AppError::Import(ImportError::EmptyNote(_, _)) => "empty_document_note",
+            AppError::Time(TimeError::AlreadyPaused) => "already_paused",
+            AppError::Time(TimeError::NotPaused) => "not_paused",
+        }
+    }
 }
 
 pub type AppResult<T> = Result<T, AppError>;
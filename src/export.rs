@@ -0,0 +1,476 @@
+//! Exporting and importing stacks.
+//!
+//! Stacks are exported as a [`Document`], which is independent of how they're
+//! stored, so it can be used to back them up or move them to another database.
+//! Documents can be written in any [`DocumentFormat`]; only JSON holds all of
+//! a document, and it looks like this:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "current_stack": "default",
+//!   "stacks": [
+//!     {
+//!       "name": "default",
+//!       "tasks": [
+//!         {"task": "shave yak", "created_at": 1700000000,
+//!          "reminders": [{"due_at": 1700003600}], "tags": ["farm"],
+//!          "notes": [{"note": "razor is in the shed", "created_at": 1700000100}],
+//!          "subtasks": [{"task": "find razor"}]}
+//!       ],
+//!       "history": [{"task": "buy yak", "status": "done", "finished_at": 1690000000}]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Tasks are listed top of stack first, and history most recently finished
+//! first. Timestamps are seconds since the Unix epoch. Everything but
+//! `version`, `stacks`, stack names and task text can be left out.
+
+use crate::commands::*;
+use crate::errors::*;
+use crate::store::*;
+use crate::types::*;
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub mod ics;
+pub mod markdown;
+pub mod todotxt;
+
+/// Version of the document format written by [`Document::to_json`].
+pub const DOCUMENT_VERSION: u32 = 1;
+
+/// Stacks, as exported.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Document {
+    pub version: u32,
+    /// Stack that was current when the document was exported, if it's in the document.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_stack: Option<String>,
+    pub stacks: Vec<StackDocument>,
+}
+
+/// A stack, as exported.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StackDocument {
+    pub name: String,
+    /// Open tasks, top of stack first.
+    #[serde(default)]
+    pub tasks: Vec<TaskDocument>,
+    /// Finished tasks, most recently finished first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<FinishedTaskDocument>,
+}
+
+/// An open task, as exported.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TaskDocument {
+    pub task: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<Timestamp>,
+    /// Pending reminders, soonest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reminders: Vec<ReminderDocument>,
+    /// Tags, including those written in the text.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<NoteDocument>,
+    /// Open tasks in the task's sub-stack, top first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subtasks: Vec<TaskDocument>,
+}
+
+/// A finished task, as exported.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FinishedTaskDocument {
+    pub task: String,
+    pub status: TaskStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<Timestamp>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<Timestamp>,
+    /// Oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<NoteDocument>,
+}
+
+/// A note on a task, as exported.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NoteDocument {
+    pub note: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<Timestamp>,
+}
+
+/// A pending reminder, as exported.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReminderDocument {
+    pub due_at: Timestamp,
+}
+
+/// Formats documents can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DocumentFormat {
+    /// Everything, as described in the [module documentation](self).
+    #[default]
+    Json,
+    /// A checklist of open tasks. See [`markdown`].
+    Markdown,
+    /// todo.txt lines, with history but without reminders or notes. See [`todotxt`].
+    Todotxt,
+    /// An iCalendar file of open tasks and their reminders. Can't be read. See [`ics`].
+    Ics,
+}
+
+impl DocumentFormat {
+    /// Check whether documents in this format can be read.
+    pub fn can_read(self) -> bool {
+        self != DocumentFormat::Ics
+    }
+}
+
+/// What to do with stacks that already exist when importing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImportMode {
+    /// Put imported tasks on top of the stack's tasks, and add to its history.
+    #[default]
+    Merge,
+    /// Replace the stack's tasks and history with the imported ones, and
+    /// switch to the document's current stack.
+    Replace,
+}
+
+impl Document {
+    /// Read a document written by [`Document::to_json`], and check it makes sense.
+    pub fn from_json(json: &str) -> Result<Document, ImportError> {
+        let value: serde_json::Value = serde_json::from_str(json).map_err(|e| ImportError::InvalidDocument(e.to_string()))?;
+        // Check the version first, so newer documents aren't reported as merely invalid.
+        let version = value.get("version").and_then(serde_json::Value::as_u64)
+            .ok_or_else(|| ImportError::InvalidDocument("missing field `version`".into()))?;
+        if version != DOCUMENT_VERSION as u64 {
+            return Err(ImportError::UnsupportedVersion { found: version as u32, supported: DOCUMENT_VERSION });
+        }
+        let document: Document = serde_json::from_value(value).map_err(|e| ImportError::InvalidDocument(e.to_string()))?;
+        document.validate()?;
+        Ok(document)
+    }
+
+    /// Read a document written in `format`.
+    ///
+    /// Formats without stack names put tasks on `default_stack`.
+    pub fn read(format: DocumentFormat, text: &str, default_stack: &str) -> Result<Document, ImportError> {
+        match format {
+            DocumentFormat::Json => Document::from_json(text),
+            DocumentFormat::Markdown => markdown::read(text, default_stack),
+            DocumentFormat::Todotxt => todotxt::read(text, default_stack),
+            DocumentFormat::Ics => Err(ImportError::InvalidDocument("iCalendar files can't be imported".into())),
+        }
+    }
+
+    /// Write the document in `format`, ending with a newline.
+    pub fn write(&self, format: DocumentFormat) -> String {
+        match format {
+            DocumentFormat::Json => self.to_json() + "\n",
+            DocumentFormat::Markdown => markdown::write(self),
+            DocumentFormat::Todotxt => todotxt::write(self),
+            DocumentFormat::Ics => ics::write(self),
+        }
+    }
+
+    /// Write the document as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("bug: document can't be serialized")
+    }
+
+    /// Check that the document can be imported.
+    pub fn validate(&self) -> Result<(), ImportError> {
+        fn validate_tasks(stack: &str, tasks: &[TaskDocument]) -> Result<(), ImportError> {
+            for task in tasks {
+                if task.task.is_empty() {
+                    return Err(ImportError::EmptyTask(stack.into()));
+                }
+                if let Some(tag) = task.tags.iter().find(|tag| !is_tag(tag)) {
+                    return Err(ImportError::InvalidTag(stack.into(), tag.clone()));
+                }
+                validate_notes(stack, &task.task, &task.notes)?;
+                validate_tasks(stack, &task.subtasks)?;
+            }
+            Ok(())
+        }
+
+        fn validate_notes(stack: &str, task: &str, notes: &[NoteDocument]) -> Result<(), ImportError> {
+            match notes.iter().any(|note| note.note.trim().is_empty()) {
+                true => Err(ImportError::EmptyNote(stack.into(), task.into())),
+                false => Ok(()),
+            }
+        }
+
+        if self.version != DOCUMENT_VERSION {
+            return Err(ImportError::UnsupportedVersion { found: self.version, supported: DOCUMENT_VERSION });
+        }
+        let mut names = HashSet::new();
+        for stack in &self.stacks {
+            if stack.name.is_empty() {
+                return Err(ImportError::EmptyStackName);
+            }
+            if !names.insert(stack.name.as_str()) {
+                return Err(ImportError::DuplicateStack(stack.name.clone()));
+            }
+            validate_tasks(&stack.name, &stack.tasks)?;
+            for task in &stack.history {
+                if task.task.is_empty() {
+                    return Err(ImportError::EmptyTask(stack.name.clone()));
+                }
+                if task.status == TaskStatus::Open {
+                    return Err(ImportError::UnfinishedHistoryTask(stack.name.clone(), task.task.clone()));
+                }
+                validate_notes(&stack.name, &task.task, &task.notes)?;
+            }
+        }
+        match &self.current_stack {
+            Some(current) if !names.contains(current.as_str()) => Err(ImportError::NoSuchCurrentStack(current.clone())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count the open tasks in the document, sub-tasks included.
+    pub fn task_count(&self) -> usize {
+        fn count(tasks: &[TaskDocument]) -> usize {
+            tasks.iter().map(|task| 1 + count(&task.subtasks)).sum()
+        }
+        self.stacks.iter().map(|stack| count(&stack.tasks)).sum()
+    }
+}
+
+/// Export the stack `stack_id`, or every stack if it's `None`.
+pub fn export<S: Store>(store: &S, stack_id: Option<StackId>) -> AppResult<Document> {
+    let current_stack_id = get_current_stack_id(store)?;
+    let mut reminders: HashMap<TaskId, Vec<ReminderDocument>> = HashMap::new();
+    for reminder in store.reminders()?.into_iter().filter(|reminder| !reminder.cancelled) {
+        reminders.entry(reminder.task_id).or_default().push(ReminderDocument { due_at: reminder.due_at });
+    }
+    let mut tags: HashMap<TaskId, Vec<String>> = HashMap::new();
+    for tag in store.tags()? {
+        tags.entry(tag.task_id).or_default().push(tag.tag);
+    }
+
+    let mut document = Document { version: DOCUMENT_VERSION, current_stack: None, stacks: Vec::new() };
+    for stack in store.stacks()?.into_iter().filter(|stack| stack_id.is_none_or(|id| stack.id == id)) {
+        if stack.id == current_stack_id {
+            document.current_stack = Some(stack.name.clone());
+        }
+        let tasks = export_tasks(store, Context { stack_id: stack.id, parent_id: None }, &mut reminders, &mut tags)?;
+        let mut history = Vec::new();
+        for task in store.finished_tasks(Some(stack.id), None, None)? {
+            history.push(FinishedTaskDocument {
+                notes: export_notes(store, task.id)?,
+                task: task.task,
+                status: task.status,
+                created_at: task.created_at,
+                finished_at: task.finished_at,
+            });
+        }
+        document.stacks.push(StackDocument { name: stack.name, tasks, history });
+    }
+    Ok(document)
+}
+
+/// Export the open tasks in `context` and their sub-stacks, top first.
+fn export_tasks<S: Store>(store: &S, context: Context, reminders: &mut HashMap<TaskId, Vec<ReminderDocument>>, tags: &mut HashMap<TaskId, Vec<String>>) -> AppResult<Vec<TaskDocument>> {
+    let mut tasks = Vec::new();
+    for task in store.open_tasks(context)?.into_iter().rev() {
+        tasks.push(TaskDocument {
+            subtasks: export_tasks(store, Context { parent_id: Some(task.id), ..context }, reminders, tags)?,
+            reminders: reminders.remove(&task.id).unwrap_or_default(),
+            tags: tags.remove(&task.id).unwrap_or_default(),
+            notes: export_notes(store, task.id)?,
+            created_at: task.created_at,
+            task: task.task,
+        });
+    }
+    Ok(tasks)
+}
+
+fn export_notes<S: Store>(store: &S, task_id: TaskId) -> AppResult<Vec<NoteDocument>> {
+    Ok(store.notes(task_id)?.into_iter().map(|note| NoteDocument { note: note.note, created_at: Some(note.created_at) }).collect())
+}
+
+/// Import the stacks in `document`, creating any that don't exist.
+///
+/// Returns the IDs of the reminders imported, which still need to be scheduled.
+pub fn import<S: Store>(store: &mut S, document: &Document, mode: ImportMode) -> AppResult<Vec<String>> {
+    document.validate()?;
+    store.transaction(|store| {
+        let mut reminder_ids = Vec::new();
+        for stack in &document.stacks {
+            let stack_id = match store.stack_by_name(&stack.name)? {
+                Some(existing) => {
+                    if mode == ImportMode::Replace {
+                        store.delete_tasks(existing.id)?;
+                    }
+                    existing.id
+                }
+                None => store.insert_stack(&stack.name)?,
+            };
+            // History goes underneath, where it can't get in the way of open tasks.
+            // Tasks not known to have finished earlier finished now, as far as we know.
+            let now = Utc::now().timestamp();
+            for task in stack.history.iter().rev() {
+                let task_id = store.insert_task(&TaskRecord {
+                    id: 0,
+                    task: task.task.clone(),
+                    order: next_order(store, Context { stack_id, parent_id: None })?,
+                    stack_id,
+                    parent_id: None,
+                    status: task.status,
+                    created_at: task.created_at,
+                    finished_at: task.finished_at.or(Some(now)),
+                })?;
+                import_notes(store, task_id, &task.notes)?;
+            }
+            import_tasks(store, &stack.tasks, Context { stack_id, parent_id: None }, &mut reminder_ids)?;
+        }
+
+        let context = get_current_context(store)?;
+        let current_stack = document.current_stack.as_deref().filter(|_| mode == ImportMode::Replace);
+        if let Some(name) = current_stack {
+            store.set_context(Context { stack_id: stack_name_to_id(store, name)?, parent_id: None })?;
+        } else if let Some(parent_id) = context.parent_id {
+            // Replacing the current stack's tasks removes the sub-stack we're in.
+            if store.task(parent_id)?.is_none() {
+                store.set_context(Context { parent_id: None, ..context })?;
+            }
+        }
+        Ok(reminder_ids)
+    })
+}
+
+/// Push `tasks`, listed top first, onto `context`, along with their sub-stacks, reminders, tags and notes.
+fn import_tasks<S: Store>(store: &mut S, tasks: &[TaskDocument], context: Context, reminder_ids: &mut Vec<String>) -> AppResult<()> {
+    for task in tasks.iter().rev() {
+        let task_id = store.insert_task(&TaskRecord {
+            id: 0,
+            task: task.task.clone(),
+            order: next_order(store, context)?,
+            stack_id: context.stack_id,
+            parent_id: context.parent_id,
+            status: TaskStatus::Open,
+            created_at: task.created_at,
+            finished_at: None,
+        })?;
+        for reminder in &task.reminders {
+            let reminder_id = Uuid::new_v4().to_string();
+            store.insert_reminder(&ReminderRecord { id: reminder_id.clone(), task_id, due_at: reminder.due_at, cancelled: false })?;
+            reminder_ids.push(reminder_id);
+        }
+        for tag in task.tags.iter().cloned().chain(inline_tags(&task.task)) {
+            store.insert_tag(&TagRecord { task_id, tag })?;
+        }
+        import_notes(store, task_id, &task.notes)?;
+        import_tasks(store, &task.subtasks, Context { parent_id: Some(task_id), ..context }, reminder_ids)?;
+    }
+    Ok(())
+}
+
+/// Add `notes` to the task `task_id`. Notes not known to have been written earlier were written now.
+fn import_notes<S: Store>(store: &mut S, task_id: TaskId, notes: &[NoteDocument]) -> AppResult<()> {
+    let now = Utc::now().timestamp();
+    for note in notes {
+        store.insert_note(&NoteRecord { id: 0, task_id, note: note.note.trim().into(), created_at: note.created_at.unwrap_or(now) })?;
+    }
+    Ok(())
+}
+
+/// Get the order of a task going on top of the other tasks in `context`.
+fn next_order<S: Store>(store: &S, context: Context) -> AppResult<f64> {
+    Ok(store.task_order_range(context)?.map_or(1., |(_, max)| max + 1.))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(text: &str) -> TaskDocument {
+        TaskDocument { task: text.into(), created_at: None, reminders: Vec::new(), tags: Vec::new(), notes: Vec::new(), subtasks: Vec::new() }
+    }
+
+    #[test]
+    fn round_trip_through_json() {
+        let mut store = MemoryStore::new();
+        push_task(&mut store, "done".into()).unwrap();
+        add_note(&mut store, TaskRef::Index(0), "how it went".into()).unwrap();
+        pop_task(&mut store).unwrap();
+        push_task(&mut store, "a".into()).unwrap();
+        push_task(&mut store, "b".into()).unwrap();
+        dive(&mut store).unwrap();
+        let b1 = push_task(&mut store, "b1".into()).unwrap();
+        store.insert_reminder(&ReminderRecord { id: "r".into(), task_id: b1.id, due_at: 100, cancelled: false }).unwrap();
+        add_note(&mut store, TaskRef::Id(b1.id), "first".into()).unwrap();
+        add_note(&mut store, TaskRef::Id(b1.id), "second".into()).unwrap();
+        new_stack(&mut store, "other".into()).unwrap();
+
+        let document = Document::from_json(&export(&store, None).unwrap().to_json()).unwrap();
+        assert_eq!(document.current_stack.as_deref(), Some("default"));
+        assert_eq!(document.stacks[0].tasks[0].task, "b");
+        assert_eq!(document.stacks[0].tasks[0].subtasks[0].reminders, vec![ReminderDocument { due_at: 100 }]);
+        let notes: Vec<&str> = document.stacks[0].tasks[0].subtasks[0].notes.iter().map(|note| note.note.as_str()).collect();
+        assert_eq!(notes, vec!["first", "second"]);
+        assert_eq!(document.stacks[0].history[0].status, TaskStatus::Done);
+        assert_eq!(document.stacks[0].history[0].notes[0].note, "how it went");
+        assert_eq!(document.stacks[1].name, "other");
+        assert_eq!(document.task_count(), 3);
+
+        let mut imported = MemoryStore::new();
+        let reminder_ids = import(&mut imported, &document, ImportMode::Replace).unwrap();
+        assert_eq!(reminder_ids.len(), 1);
+        assert_eq!(export(&imported, None).unwrap(), document);
+    }
+
+    #[test]
+    fn merge_puts_tasks_on_top() {
+        let mut store = MemoryStore::new();
+        push_task(&mut store, "mine".into()).unwrap();
+        let document = Document {
+            version: DOCUMENT_VERSION,
+            current_stack: Some("new".into()),
+            stacks: vec![
+                StackDocument { name: "default".into(), tasks: vec![task("top"), task("theirs")], history: Vec::new() },
+                StackDocument { name: "new".into(), tasks: vec![task("x")], history: Vec::new() },
+            ],
+        };
+        import(&mut store, &document, ImportMode::Merge).unwrap();
+        let tasks: Vec<String> = list_tasks(&store).unwrap().into_iter().map(|task| task.task).collect();
+        assert_eq!(tasks, vec!["mine", "theirs", "top"]);
+        assert_eq!(get_current_stack_name(&store).unwrap(), "default");
+
+        import(&mut store, &document, ImportMode::Replace).unwrap();
+        assert_eq!(get_current_stack_name(&store).unwrap(), "new");
+        assert_eq!(list_tasks(&store).unwrap().len(), 1);
+        switch_to_stack(&mut store, "default".into()).unwrap();
+        let tasks: Vec<String> = list_tasks(&store).unwrap().into_iter().map(|task| task.task).collect();
+        assert_eq!(tasks, vec!["theirs", "top"]);
+    }
+
+    #[test]
+    fn invalid_documents_are_rejected() {
+        let error = |json: &str| Document::from_json(json).unwrap_err();
+        assert!(matches!(error("[1, 2"), ImportError::InvalidDocument(_)));
+        assert!(matches!(error(r#"{"stacks": []}"#), ImportError::InvalidDocument(_)));
+        assert!(matches!(error(r#"{"version": 2, "whatever": true}"#), ImportError::UnsupportedVersion { found: 2, .. }));
+        assert!(matches!(error(r#"{"version": 1, "stacks": [{"name": ""}]}"#), ImportError::EmptyStackName));
+        assert!(matches!(error(r#"{"version": 1, "stacks": [{"name": "a"}, {"name": "a"}]}"#), ImportError::DuplicateStack(_)));
+        assert!(matches!(error(r#"{"version": 1, "stacks": [{"name": "a", "tasks": [{"task": "x", "subtasks": [{"task": ""}]}]}]}"#), ImportError::EmptyTask(_)));
+        assert!(matches!(error(r#"{"version": 1, "stacks": [{"name": "a", "history": [{"task": "x", "status": "open"}]}]}"#), ImportError::UnfinishedHistoryTask(..)));
+        assert!(matches!(error(r#"{"version": 1, "stacks": [{"name": "a", "tasks": [{"task": "x", "notes": [{"note": " "}]}]}]}"#), ImportError::EmptyNote(..)));
+        assert!(matches!(error(r#"{"version": 1, "current_stack": "b", "stacks": [{"name": "a"}]}"#), ImportError::NoSuchCurrentStack(_)));
+        assert!(Document::from_json(r#"{"version": 1, "stacks": [{"name": "a"}]}"#).is_ok());
+    }
+}
@@ -0,0 +1,183 @@
+//! iCalendar files, for calendar and to-do apps. Export only.
+//!
+//! Every open task is a `VTODO`, with its stack and tags as its `CATEGORIES`, its
+//! notes as its `DESCRIPTION` and sub-tasks `RELATED-TO` their parent. Each pending reminder is a `VALARM`
+//! on its task, triggered at the reminder's due time. A task's `UID` is made
+//! from its stack, text and creation time, so it stays the same from one
+//! export to the next and apps can update the tasks they already have.
+
+use super::*;
+
+use chrono::{DateTime, Utc};
+
+/// Longest a content line can be, in bytes, before it's folded onto the next.
+const MAX_LINE_LENGTH: usize = 75;
+
+/// Write the open tasks and pending reminders in `document` as a calendar.
+pub fn write(document: &Document) -> String {
+    write_at(document, Utc::now().timestamp())
+}
+
+/// Write `document` as a calendar exported at `now`.
+fn write_at(document: &Document, now: Timestamp) -> String {
+    let mut calendar = Calendar { out: String::new(), uids: HashSet::new() };
+    calendar.line("BEGIN:VCALENDAR");
+    calendar.line("VERSION:2.0");
+    calendar.line(&format!("PRODID:-//yakstack//yakstack {}//EN", env!("CARGO_PKG_VERSION")));
+    for stack in &document.stacks {
+        calendar.todos(&stack.name, &stack.tasks, None, now);
+    }
+    calendar.line("END:VCALENDAR");
+    calendar.out
+}
+
+/// A calendar being written.
+struct Calendar {
+    out: String,
+    /// UIDs given out so far.
+    uids: HashSet<String>,
+}
+
+impl Calendar {
+    /// Write a `VTODO` for each of `tasks` and their sub-tasks.
+    fn todos(&mut self, stack: &str, tasks: &[TaskDocument], parent_uid: Option<&str>, now: Timestamp) {
+        for task in tasks {
+            let uid = self.uid(stack, task);
+            self.line("BEGIN:VTODO");
+            self.line(&format!("UID:{}", uid));
+            self.line(&format!("DTSTAMP:{}", date_time(now)));
+            if let Some(created_at) = task.created_at {
+                self.line(&format!("CREATED:{}", date_time(created_at)));
+            }
+            self.line(&format!("SUMMARY:{}", escape(&task.task)));
+            if !task.notes.is_empty() {
+                let notes: Vec<&str> = task.notes.iter().map(|note| note.note.as_str()).collect();
+                self.line(&format!("DESCRIPTION:{}", escape(&notes.join("\n\n"))));
+            }
+            let categories: Vec<String> = std::iter::once(stack).chain(task.tags.iter().map(String::as_str)).map(escape).collect();
+            self.line(&format!("CATEGORIES:{}", categories.join(",")));
+            self.line("STATUS:NEEDS-ACTION");
+            if let Some(parent_uid) = parent_uid {
+                self.line(&format!("RELATED-TO;RELTYPE=PARENT:{}", parent_uid));
+            }
+            for reminder in &task.reminders {
+                self.line("BEGIN:VALARM");
+                self.line("ACTION:DISPLAY");
+                self.line(&format!("DESCRIPTION:{}", escape(&task.task)));
+                self.line(&format!("TRIGGER;VALUE=DATE-TIME:{}", date_time(reminder.due_at)));
+                self.line("END:VALARM");
+            }
+            self.line("END:VTODO");
+            self.todos(stack, &task.subtasks, Some(&uid), now);
+        }
+    }
+
+    /// Make up a UID for `task` that no other task has.
+    fn uid(&mut self, stack: &str, task: &TaskDocument) -> String {
+        // FNV-1a, which unlike std's hasher is the same everywhere, every time.
+        let key = format!("{}\0{}\0{}", stack, task.created_at.unwrap_or_default(), task.task);
+        let hash = key.bytes().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+        let mut uid = format!("{:016x}@yakstack", hash);
+        let mut copy = 1;
+        while !self.uids.insert(uid.clone()) {
+            copy += 1;
+            uid = format!("{:016x}-{}@yakstack", hash, copy);
+        }
+        uid
+    }
+
+    /// Write a content line, folding it if it's too long.
+    fn line(&mut self, line: &str) {
+        let mut start = 0;
+        let mut length = 0;
+        for (i, c) in line.char_indices() {
+            // Continuation lines start with a space, which counts towards their length.
+            if length + c.len_utf8() > MAX_LINE_LENGTH {
+                self.out.push_str(&line[start..i]);
+                self.out.push_str("\r\n ");
+                start = i;
+                length = 1;
+            }
+            length += c.len_utf8();
+        }
+        self.out.push_str(&line[start..]);
+        self.out.push_str("\r\n");
+    }
+}
+
+/// Escape `text` for use as a property value.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | ';' | ',' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Write `timestamp` as a UTC date and time.
+fn date_time(timestamp: Timestamp) -> String {
+    DateTime::from_timestamp(timestamp, 0).unwrap_or_default().format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(text: &str, reminders: Vec<ReminderDocument>, subtasks: Vec<TaskDocument>) -> TaskDocument {
+        TaskDocument { task: text.into(), created_at: Some(0), reminders, tags: Vec::new(), notes: Vec::new(), subtasks }
+    }
+
+    #[test]
+    fn tasks_become_todos_with_alarms() {
+        let document = Document {
+            version: DOCUMENT_VERSION,
+            current_stack: None,
+            stacks: vec![StackDocument {
+                name: "work, mostly".into(),
+                tasks: vec![
+                    task("shave yak", vec![ReminderDocument { due_at: 1800000000 }], vec![task("find razor", Vec::new(), Vec::new())]),
+                    TaskDocument {
+                        notes: vec![NoteDocument { note: "see #12, maybe".into(), created_at: None }, NoteDocument { note: "or not".into(), created_at: None }],
+                        ..task("shave yak", Vec::new(), Vec::new())
+                    },
+                ],
+                history: vec![FinishedTaskDocument { task: "done".into(), status: TaskStatus::Done, created_at: None, finished_at: None, notes: Vec::new() }],
+            }],
+        };
+        let ics = write_at(&document, 1700000000);
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+        assert!(ics.ends_with("END:VCALENDAR\r\n"));
+        assert_eq!(ics.matches("BEGIN:VTODO").count(), 3);
+        assert!(!ics.contains("SUMMARY:done"));
+        assert!(ics.contains("DTSTAMP:20231114T221320Z\r\n"));
+        assert!(ics.contains("CATEGORIES:work\\, mostly\r\n"));
+        assert!(ics.contains("DESCRIPTION:see #12\\, maybe\\n\\nor not\r\n"));
+        assert!(ics.contains("BEGIN:VALARM\r\nACTION:DISPLAY\r\nDESCRIPTION:shave yak\r\nTRIGGER;VALUE=DATE-TIME:20270115T080000Z\r\nEND:VALARM\r\n"));
+
+        let uids: Vec<&str> = ics.lines().filter_map(|line| line.strip_prefix("UID:")).collect();
+        assert_eq!(uids.len(), 3);
+        assert!(uids[2].ends_with("-2@yakstack"));
+        assert!(ics.contains(&format!("RELATED-TO;RELTYPE=PARENT:{}\r\n", uids[0])));
+        // The same tasks get the same UIDs next time.
+        assert_eq!(write_at(&document, 1700000000), ics);
+    }
+
+    #[test]
+    fn long_lines_are_folded() {
+        let mut calendar = Calendar { out: String::new(), uids: HashSet::new() };
+        calendar.line(&format!("SUMMARY:{}", "é".repeat(40)));
+        let lines: Vec<&str> = calendar.out.split("\r\n").collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines.iter().all(|line| line.len() <= MAX_LINE_LENGTH));
+        assert!(lines[1].starts_with(' '));
+        assert_eq!(lines[0].to_string() + &lines[1][1..], format!("SUMMARY:{}", "é".repeat(40)));
+    }
+}
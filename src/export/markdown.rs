@@ -0,0 +1,158 @@
+//! Markdown checklists.
+//!
+//! Each stack is a heading followed by a list of its open tasks, top first,
+//! with sub-stacks as nested lists:
+//!
+//! ```markdown
+//! # default
+//!
+//! - [ ] shave yak
+//!   - [ ] find razor
+//! - [ ] buy yak
+//! ```
+//!
+//! When reading, any heading level starts a stack, and items before the first
+//! heading go on the stack given by the caller. Items can be marked with `-`,
+//! `*` or `+`, with or without a checkbox; checked items are read as done and
+//! go into the stack's history. Other lines are ignored.
+//!
+//! Tags are written as `#tag`s in the item's text, where they're read back
+//! from like any inline tag.
+
+use super::*;
+
+/// Spaces to indent each level of sub-stack by.
+const INDENT: usize = 2;
+
+/// Write `document` as a checklist. History, reminders and timestamps are left out.
+pub fn write(document: &Document) -> String {
+    fn write_tasks(out: &mut String, tasks: &[TaskDocument], depth: usize) {
+        for task in tasks {
+            // Items can't span lines.
+            let mut text = task.task.replace(['\r', '\n'], " ");
+            let inline = inline_tags(&text);
+            for tag in task.tags.iter().filter(|tag| !inline.contains(tag)) {
+                text.push_str(&format!(" #{}", tag));
+            }
+            out.push_str(&format!("{:indent$}- [ ] {}\n", "", text, indent = depth * INDENT));
+            write_tasks(out, &task.subtasks, depth + 1);
+        }
+    }
+
+    let mut out = String::new();
+    for (i, stack) in document.stacks.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        out.push_str(&format!("# {}\n", stack.name));
+        if !stack.tasks.is_empty() {
+            out.push('\n');
+            write_tasks(&mut out, &stack.tasks, 0);
+        }
+    }
+    out
+}
+
+/// Read a checklist, putting items before the first heading on `default_stack`.
+pub fn read(markdown: &str, default_stack: &str) -> Result<Document, ImportError> {
+    let mut stacks: Vec<StackDocument> = Vec::new();
+    // Indentation of each item enclosing the next one, outermost first.
+    let mut open_items: Vec<usize> = Vec::new();
+    for line in markdown.lines() {
+        if let Some(name) = heading(line) {
+            stacks.push(StackDocument { name: name.into(), tasks: Vec::new(), history: Vec::new() });
+            open_items.clear();
+            continue;
+        }
+        let Some((indent, checked, text)) = list_item(line) else {
+            continue;
+        };
+        if stacks.is_empty() {
+            stacks.push(StackDocument { name: default_stack.into(), tasks: Vec::new(), history: Vec::new() });
+        }
+        let stack = stacks.last_mut().unwrap();
+        while open_items.last().is_some_and(|&enclosing| enclosing >= indent) {
+            open_items.pop();
+        }
+        if checked {
+            stack.history.insert(0, FinishedTaskDocument {
+                task: text.into(),
+                status: TaskStatus::Done,
+                created_at: None,
+                finished_at: None,
+                notes: Vec::new(),
+            });
+            continue;
+        }
+        let mut siblings = &mut stack.tasks;
+        for _ in 0..open_items.len() {
+            siblings = &mut siblings.last_mut().unwrap().subtasks;
+        }
+        siblings.push(TaskDocument { task: text.into(), created_at: None, reminders: Vec::new(), tags: inline_tags(text), notes: Vec::new(), subtasks: Vec::new() });
+        open_items.push(indent);
+    }
+
+    let document = Document { version: DOCUMENT_VERSION, current_stack: None, stacks };
+    document.validate()?;
+    Ok(document)
+}
+
+/// Get the text of `line` if it's a heading.
+fn heading(line: &str) -> Option<&str> {
+    let text = line.trim_start_matches('#');
+    let level = line.len() - text.len();
+    if !(1..=6).contains(&level) || !(text.is_empty() || text.starts_with(' ')) {
+        return None;
+    }
+    // Headings can be closed with any number of #s.
+    let text = text.trim();
+    match text.trim_end_matches('#') {
+        unclosed if unclosed.is_empty() || unclosed.ends_with(' ') => Some(unclosed.trim_end()),
+        _ => Some(text),
+    }
+}
+
+/// Split `line`, if it's a list item, into its indentation, whether it's checked, and its text.
+fn list_item(line: &str) -> Option<(usize, bool, &str)> {
+    let item = line.trim_start();
+    // Tabs count as 4 spaces, which is close enough for lists.
+    let indent = line[..line.len() - item.len()].chars().map(|c| if c == '\t' { 4 } else { 1 }).sum();
+    let text = item.strip_prefix(['-', '*', '+'])?.strip_prefix(' ')?.trim();
+    let (checked, text) = match text.get(..3) {
+        Some("[ ]") => (false, &text[3..]),
+        Some("[x]" | "[X]") => (true, &text[3..]),
+        _ => (false, text),
+    };
+    Some((indent, checked, text.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_checklist() {
+        let markdown = "# default\n\n- [ ] b\n  - [ ] b2\n    - [ ] b2a\n  - [ ] b1\n- [ ] a\n\n# empty\n";
+        let document = read(markdown, "unused").unwrap();
+        assert_eq!(document.stacks[0].tasks[0].subtasks[0].subtasks[0].task, "b2a");
+        assert_eq!(document.stacks[0].tasks[1].task, "a");
+        assert_eq!(write(&document), markdown);
+    }
+
+    #[test]
+    fn read_loose_checklist() {
+        let markdown = "Some yaks:\n\n* top\n\t+ [x] done\n\t+ sub\n- [X] also done\n-not an item\n## work ##\n- [ ]   spaced  \n";
+        let document = read(markdown, "current").unwrap();
+        assert_eq!(document.stacks.len(), 2);
+        let current = &document.stacks[0];
+        assert_eq!(current.name, "current");
+        assert_eq!(current.tasks.len(), 1);
+        assert_eq!(current.tasks[0].subtasks[0].task, "sub");
+        let history: Vec<&str> = current.history.iter().map(|task| task.task.as_str()).collect();
+        assert_eq!(history, vec!["also done", "done"]);
+        assert_eq!(document.stacks[1].name, "work");
+        assert_eq!(document.stacks[1].tasks[0].task, "spaced");
+        assert!(matches!(read("- [ ] \n", "a"), Err(ImportError::EmptyTask(_))));
+        assert!(matches!(read("# a\n# a #\n", "a"), Err(ImportError::DuplicateStack(_))));
+    }
+}
@@ -0,0 +1,298 @@
+//! todo.txt files.
+//!
+//! Every task is a line, with its stack as a `+project` tag:
+//!
+//! ```text
+//! (A) 2026-10-01 shave yak +default pos:0 id:1
+//! (A) 2026-10-02 find razor +default pos:0 parent:1
+//! (B) 2026-09-30 buy yak +default pos:1
+//! x 2026-10-03 2026-10-01 feed yak +default
+//! ```
+//!
+//! `pos:` counts from 0 at the top of the task's (sub-)stack, and the top 26
+//! tasks of each also get priorities A to Z, so other todo.txt tools sort them
+//! the same way. Tasks with sub-stacks get an `id:`, which their sub-tasks
+//! refer to with `parent:`. Finished tasks are `x` lines with their
+//! completion and creation dates; abandoned ones also get `status:abandoned`.
+//! Stack names can't contain spaces here, so spaces become underscores. Tags
+//! not written in a task's text are `@contexts`.
+//!
+//! When reading, a task's last `+project` is its stack, and tasks without one
+//! go on the stack given by the caller. Tasks are ordered by priority, then
+//! `pos:`, then where they are in the file, with missing priorities and
+//! positions last. Contexts that are valid tags become tags; any other tags
+//! and metadata stay in the task's text.
+
+use super::*;
+
+use crate::timespec;
+
+use chrono::{Local, NaiveDate, TimeZone};
+
+/// Write `document` as todo.txt lines. Reminders are left out.
+pub fn write(document: &Document) -> String {
+    let mut out = String::new();
+    let mut next_id = 1;
+    for stack in &document.stacks {
+        let project = format!("+{}", stack.name.replace(char::is_whitespace, "_"));
+        write_tasks(&mut out, &stack.tasks, &project, None, &mut next_id);
+        for task in &stack.history {
+            out.push('x');
+            if let Some(finished_at) = task.finished_at {
+                out.push_str(&format!(" {}", date(finished_at)));
+                if let Some(created_at) = task.created_at {
+                    out.push_str(&format!(" {}", date(created_at)));
+                }
+            }
+            out.push_str(&format!(" {} {}", single_line(&task.task), project));
+            if task.status == TaskStatus::Abandoned {
+                out.push_str(" status:abandoned");
+            }
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Write `tasks`, listed top first, and their sub-tasks.
+fn write_tasks(out: &mut String, tasks: &[TaskDocument], project: &str, parent_id: Option<u32>, next_id: &mut u32) {
+    for (pos, task) in tasks.iter().enumerate() {
+        if let Some(priority) = (b'A'..=b'Z').nth(pos) {
+            out.push_str(&format!("({}) ", priority as char));
+        }
+        if let Some(created_at) = task.created_at {
+            out.push_str(&format!("{} ", date(created_at)));
+        }
+        out.push_str(&format!("{} {} pos:{}", single_line(&task.task), project, pos));
+        let inline = inline_tags(&task.task);
+        for tag in task.tags.iter().filter(|tag| !inline.contains(tag)) {
+            out.push_str(&format!(" @{}", tag));
+        }
+        let id = (!task.subtasks.is_empty()).then(|| {
+            *next_id += 1;
+            *next_id - 1
+        });
+        if let Some(id) = id {
+            out.push_str(&format!(" id:{}", id));
+        }
+        if let Some(parent_id) = parent_id {
+            out.push_str(&format!(" parent:{}", parent_id));
+        }
+        out.push('\n');
+        if let Some(id) = id {
+            write_tasks(out, &task.subtasks, project, Some(id), next_id);
+        }
+    }
+}
+
+/// A task read from a line.
+struct Line {
+    stack: String,
+    task: String,
+    done: bool,
+    abandoned: bool,
+    priority: Option<char>,
+    pos: Option<u64>,
+    id: Option<String>,
+    parent: Option<String>,
+    tags: Vec<String>,
+    created_at: Option<Timestamp>,
+    finished_at: Option<Timestamp>,
+}
+
+/// Read todo.txt lines, putting tasks without a project on `default_stack`.
+pub fn read(todotxt: &str, default_stack: &str) -> Result<Document, ImportError> {
+    let lines: Vec<Line> = todotxt.lines().filter(|line| !line.trim().is_empty()).map(|line| parse_line(line, default_stack)).collect();
+
+    let mut stacks: Vec<StackDocument> = Vec::new();
+    for line in &lines {
+        if !stacks.iter().any(|stack| stack.name == line.stack) {
+            stacks.push(StackDocument { name: line.stack.clone(), tasks: Vec::new(), history: Vec::new() });
+        }
+    }
+    for stack in &mut stacks {
+        let mut history: Vec<&Line> = lines.iter().filter(|line| line.done && line.stack == stack.name).collect();
+        history.sort_by_key(|line| std::cmp::Reverse(line.finished_at));
+        stack.history = history.into_iter().map(|line| FinishedTaskDocument {
+            task: line.task.clone(),
+            status: if line.abandoned { TaskStatus::Abandoned } else { TaskStatus::Done },
+            created_at: line.created_at,
+            finished_at: line.finished_at,
+            notes: Vec::new(),
+        }).collect();
+
+        let open: Vec<usize> = (0..lines.len()).filter(|&i| !lines[i].done && lines[i].stack == stack.name).collect();
+        let ids: HashMap<&str, usize> = open.iter().filter_map(|&i| Some((lines[i].id.as_deref()?, i))).collect();
+        // Tasks whose parent isn't an open task on the same stack go on the stack itself.
+        let parent_of = |i: usize| lines[i].parent.as_deref().and_then(|parent| ids.get(parent).copied()).filter(|&parent| parent != i);
+        let mut visited = HashSet::new();
+        let roots: Vec<usize> = open.iter().copied().filter(|&i| parent_of(i).is_none()).collect();
+        stack.tasks = build_tasks(&lines, &roots, &open, &parent_of, &mut visited);
+        // Tasks whose parents form a cycle are never reached from the stack, so
+        // break each cycle by putting one of its tasks on the stack.
+        while let Some(unreached) = open.iter().copied().find(|i| !visited.contains(i)) {
+            stack.tasks.extend(build_tasks(&lines, &[unreached], &open, &parent_of, &mut visited));
+        }
+    }
+
+    let document = Document { version: DOCUMENT_VERSION, current_stack: None, stacks };
+    document.validate()?;
+    Ok(document)
+}
+
+/// Build the tasks on `lines` with indices in `siblings`, top first, along with their sub-tasks.
+fn build_tasks(lines: &[Line], siblings: &[usize], open: &[usize], parent_of: &dyn Fn(usize) -> Option<usize>, visited: &mut HashSet<usize>) -> Vec<TaskDocument> {
+    let mut siblings: Vec<usize> = siblings.iter().copied().filter(|i| !visited.contains(i)).collect();
+    siblings.sort_by_key(|&i| (lines[i].priority.is_none(), lines[i].priority, lines[i].pos.is_none(), lines[i].pos, i));
+    visited.extend(&siblings);
+    siblings.into_iter().map(|i| {
+        let children: Vec<usize> = open.iter().copied().filter(|&child| parent_of(child) == Some(i)).collect();
+        TaskDocument {
+            task: lines[i].task.clone(),
+            created_at: lines[i].created_at,
+            reminders: Vec::new(),
+            tags: lines[i].tags.clone(),
+            notes: Vec::new(),
+            subtasks: build_tasks(lines, &children, open, parent_of, visited),
+        }
+    }).collect()
+}
+
+/// Read a task from `line`.
+fn parse_line(line: &str, default_stack: &str) -> Line {
+    let mut words = line.split_whitespace().peekable();
+    let done = words.next_if_eq(&"x").is_some();
+    let priority = words.next_if(|word| is_priority(word)).and_then(|word| word.chars().nth(1));
+    let first_date = words.next_if(|word| parse_date(word).is_some()).and_then(parse_date);
+    let second_date = words.next_if(|word| done && parse_date(word).is_some()).and_then(parse_date);
+    // Completed tasks have their completion date first.
+    let (created_at, finished_at) = match (done, first_date, second_date) {
+        (true, finished_at, Some(created_at)) => (Some(created_at), finished_at),
+        (true, finished_at, None) => (None, finished_at),
+        (false, created_at, _) => (created_at, None),
+    };
+
+    let mut parsed = Line {
+        stack: String::new(),
+        task: String::new(),
+        done,
+        abandoned: false,
+        priority,
+        pos: None,
+        id: None,
+        parent: None,
+        tags: Vec::new(),
+        created_at,
+        finished_at,
+    };
+    // The project is written after the text, which can have +words of its own.
+    let words: Vec<&str> = words.collect();
+    let project = words.iter().rposition(|word| word.len() > 1 && word.starts_with('+'));
+    let mut text = Vec::new();
+    for (i, word) in words.into_iter().enumerate() {
+        match word.split_once(':') {
+            _ if Some(i) == project => parsed.stack = word[1..].into(),
+            Some(("pos", pos)) if pos.parse::<u64>().is_ok() => parsed.pos = pos.parse().ok(),
+            Some(("id", id)) => parsed.id = Some(id.into()),
+            Some(("parent", parent)) => parsed.parent = Some(parent.into()),
+            Some(("status", "abandoned")) => parsed.abandoned = true,
+            _ if word.strip_prefix('@').is_some_and(is_tag) => parsed.tags.push(word[1..].into()),
+            // Priorities of completed tasks are sometimes kept as metadata.
+            Some(("pri", _)) => {}
+            _ => text.push(word),
+        }
+    }
+    if parsed.stack.is_empty() {
+        parsed.stack = default_stack.into();
+    }
+    parsed.task = text.join(" ");
+    parsed.tags.extend(inline_tags(&parsed.task));
+    parsed.tags.sort();
+    parsed.tags.dedup();
+    parsed
+}
+
+/// Check whether `word` is a priority like `(A)`.
+fn is_priority(word: &str) -> bool {
+    matches!(word.as_bytes(), [b'(', b'A'..=b'Z', b')'])
+}
+
+/// Read a `YYYY-MM-DD` date as the first moment of that day in local time.
+fn parse_date(word: &str) -> Option<Timestamp> {
+    NaiveDate::parse_from_str(word, "%Y-%m-%d").ok().map(timespec::start_of_day)
+}
+
+/// Write `timestamp` as a `YYYY-MM-DD` date in local time.
+fn date(timestamp: Timestamp) -> String {
+    Local.timestamp_opt(timestamp, 0).single().map_or_else(String::new, |time| time.format("%Y-%m-%d").to_string())
+}
+
+/// Make `text` fit on one line.
+fn single_line(text: &str) -> String {
+    text.replace(['\r', '\n'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(text: &str, subtasks: Vec<TaskDocument>) -> TaskDocument {
+        TaskDocument { task: text.into(), created_at: None, reminders: Vec::new(), tags: Vec::new(), notes: Vec::new(), subtasks }
+    }
+
+    #[test]
+    fn round_trip_stacks() {
+        let day = parse_date("2026-10-01").unwrap();
+        let tasks: Vec<TaskDocument> = (0..30).map(|i| task(&i.to_string(), Vec::new())).collect();
+        let document = Document {
+            version: DOCUMENT_VERSION,
+            current_stack: None,
+            stacks: vec![
+                StackDocument {
+                    name: "default".into(),
+                    tasks: vec![
+                        task("b", vec![task("b2", Vec::new()), task("b1", vec![task("b1a", Vec::new())])]),
+                        TaskDocument { tags: vec!["home".into(), "yak".into()], ..task("a #yak", Vec::new()) },
+                        task("give +1 to the PR", Vec::new()),
+                    ],
+                    history: vec![
+                        FinishedTaskDocument { task: "ask +team about it".into(), status: TaskStatus::Done, created_at: None, finished_at: Some(day + 86400), notes: Vec::new() },
+                        FinishedTaskDocument { task: "done".into(), status: TaskStatus::Done, created_at: Some(day), finished_at: Some(day + 86400), notes: Vec::new() },
+                        FinishedTaskDocument { task: "dropped".into(), status: TaskStatus::Abandoned, created_at: None, finished_at: Some(day), notes: Vec::new() },
+                    ],
+                },
+                StackDocument { name: "many".into(), tasks, history: Vec::new() },
+            ],
+        };
+        let todotxt = write(&document);
+        assert!(todotxt.starts_with("(A) b +default pos:0 id:1\n(A) b2 +default pos:0 parent:1\n"));
+        assert!(todotxt.contains("\nx 2026-10-02 2026-10-01 done +default\n"));
+        assert!(todotxt.contains("\n29 +many pos:29\n"));
+        assert!(todotxt.contains("\n(B) a #yak +default pos:1 @home\n"));
+        assert!(todotxt.contains("\n(C) give +1 to the PR +default pos:2\n"));
+        assert_eq!(read(&todotxt, "unused").unwrap(), document);
+    }
+
+    #[test]
+    fn read_other_tools_lines() {
+        let todotxt = "call mum @phone\n(B) 2026-10-01 file taxes +home due:2026-11-01\n(A) fix roof +home\nx 2026-10-05 pay rent +home pri:A\n\nwalk dog +home pos:0 parent:7\n";
+        let document = read(todotxt, "current").unwrap();
+        assert_eq!(document.stacks[0].name, "current");
+        assert_eq!(document.stacks[0].tasks[0].task, "call mum");
+        assert_eq!(document.stacks[0].tasks[0].tags, vec!["phone"]);
+        let home = &document.stacks[1];
+        let tasks: Vec<&str> = home.tasks.iter().map(|task| task.task.as_str()).collect();
+        assert_eq!(tasks, vec!["fix roof", "file taxes due:2026-11-01", "walk dog"]);
+        assert_eq!(home.tasks[1].created_at, parse_date("2026-10-01"));
+        assert_eq!(home.history[0].task, "pay rent");
+        assert_eq!(home.history[0].finished_at, parse_date("2026-10-05"));
+        assert!(matches!(read("(A) +home\n", "a"), Err(ImportError::EmptyTask(_))));
+    }
+
+    #[test]
+    fn cycles_of_parents_are_broken() {
+        let document = read("a +s id:1 parent:2\nb +s id:2 parent:1\nc +s id:3 parent:3\n", "unused").unwrap();
+        assert_eq!(document.task_count(), 3);
+        assert_eq!(document.stacks[0].tasks.len(), 2);
+    }
+}
@@ -0,0 +1,233 @@
+//! Undo/redo journal.
+//!
+//! Every mutating command runs in a transaction inside [`record`], which
+//! installs temporary triggers on the journaled tables for the duration of the
+//! command. The triggers write a pair of SQL statements per changed row into
+//! `journal_changes`: one that reverts the change and one that reapplies it.
+//! Undoing a command replays its undo statements newest first; redoing replays
+//! its redo statements oldest first.
+//!
+//! The triggers are TEMP, so they only see changes made through the recording
+//! connection, and they are generated from the live table definitions, so
+//! migrations adding columns need no journal-specific changes.
+//!
+//! Time tracking isn't journaled: time spent on a task stays spent when the
+//! command that put the task on top is undone. If undoing deletes the task,
+//! its time is kept without a task, and redoing doesn't give it back.
+
+use crate::errors::*;
+#[cfg(test)]
+use crate::store::{SqliteStore, Store};
+use crate::types::*;
+
+use chrono::Utc;
+use rusqlite::{Connection, params, OptionalExtension};
+
+/// Tables whose changes can be undone.
+static JOURNALED_TABLES: &[&str] = &["stacks", "app_state", "tasks", "reminders", "bindings", "tags", "notes"];
+
+/// How many commands to remember.
+const JOURNAL_LENGTH: u32 = 200;
+
+/// Run `f` as the command `command` in a transaction, recording its changes so they can be undone.
+///
+/// Commands that fail or don't change anything are not recorded. Recording a command
+/// discards any undone commands, so they can no longer be redone.
+#[cfg(test)]
+pub fn record<T>(store: &mut SqliteStore, command: &str, f: impl FnOnce(&mut SqliteStore) -> AppResult<T>) -> AppResult<T> {
+    store.transaction(|store| {
+        let journal_id = begin(store.connection(), command)?;
+        let result = f(store)?;
+        finish(store.connection(), journal_id)?;
+        Ok(result)
+    })
+}
+
+/// Start recording the changes made by the command `command`, returning its journal entry's ID.
+///
+/// Every change made through `db` is recorded until [`finish`] is called.
+pub fn begin(db: &Connection, command: &str) -> AppResult<i64> {
+    let created_at: Timestamp = Utc::now().timestamp();
+    db.execute("INSERT INTO journal(command, created_at) VALUES (?, ?)", params![command, created_at])?;
+    let journal_id = db.last_insert_rowid();
+    install_triggers(db, journal_id)?;
+    Ok(journal_id)
+}
+
+/// Stop recording changes for the journal entry `journal_id`.
+///
+/// Commands that don't change anything are not recorded. Recording a command
+/// discards any undone commands, so they can no longer be redone.
+pub fn finish(db: &Connection, journal_id: i64) -> AppResult<()> {
+    drop_triggers(db)?;
+    let num_changes: u32 = db.query_row("SELECT count(*) FROM journal_changes WHERE journal_id = ?", params![journal_id], |row| row.get(0))?;
+    if num_changes == 0 {
+        db.execute("DELETE FROM journal WHERE id = ?", params![journal_id])?;
+    } else {
+        db.execute("DELETE FROM journal WHERE undone = 1", [])?;
+        db.execute("DELETE FROM journal WHERE id NOT IN (SELECT id FROM journal ORDER BY id DESC LIMIT ?)", params![JOURNAL_LENGTH])?;
+    }
+    Ok(())
+}
+
+/// Undo the most recent command that hasn't been undone.
+///
+/// Returns the undone command.
+pub fn undo(db: &mut Connection) -> AppResult<String> {
+    let xact = db.transaction()?;
+    let entry: Option<(i64, String)> = xact.query_row("SELECT id, command FROM journal WHERE undone = 0 ORDER BY id DESC LIMIT 1",
+        [], |row| Ok((row.get(0)?, row.get(1)?))).optional()?;
+    let Some((journal_id, command)) = entry else {
+        return Err(JournalError::NothingToUndo.into());
+    };
+    replay(&xact, "SELECT undo_sql FROM journal_changes WHERE journal_id = ? ORDER BY id DESC", journal_id)?;
+    xact.execute("UPDATE journal SET undone = 1 WHERE id = ?", params![journal_id])?;
+    xact.commit()?;
+    Ok(command)
+}
+
+/// Redo the least recently undone command.
+///
+/// Returns the redone command.
+pub fn redo(db: &mut Connection) -> AppResult<String> {
+    let xact = db.transaction()?;
+    let entry: Option<(i64, String)> = xact.query_row("SELECT id, command FROM journal WHERE undone = 1 ORDER BY id ASC LIMIT 1",
+        [], |row| Ok((row.get(0)?, row.get(1)?))).optional()?;
+    let Some((journal_id, command)) = entry else {
+        return Err(JournalError::NothingToRedo.into());
+    };
+    replay(&xact, "SELECT redo_sql FROM journal_changes WHERE journal_id = ? ORDER BY id ASC", journal_id)?;
+    xact.execute("UPDATE journal SET undone = 0 WHERE id = ?", params![journal_id])?;
+    xact.commit()?;
+    Ok(command)
+}
+
+/// Run every statement selected by `query` for the journal entry `journal_id`.
+fn replay(db: &Connection, query: &str, journal_id: i64) -> AppResult<()> {
+    // Rows come back in whatever order they were changed in, which may not
+    // satisfy foreign keys until the whole entry has been replayed.
+    db.execute("PRAGMA defer_foreign_keys = ON", [])?;
+    let statements = {
+        let mut stmt = db.prepare(query)?;
+        let statements = stmt.query_map(params![journal_id], |row| row.get(0))?.collect::<Result<Vec<String>, _>>()?;
+        statements
+    };
+    for statement in statements {
+        db.execute_batch(&statement)?;
+    }
+    Ok(())
+}
+
+/// Create triggers recording every change to the journaled tables under `journal_id`.
+fn install_triggers(db: &Connection, journal_id: i64) -> AppResult<()> {
+    drop_triggers(db)?;
+    for &table in JOURNALED_TABLES {
+        let columns = {
+            let mut stmt = db.prepare(&format!("PRAGMA main.table_info({})", table))?;
+            let columns = stmt.query_map([], |row| row.get(1))?.collect::<Result<Vec<String>, _>>()?;
+            columns
+        };
+        let column_list = columns.join(", ");
+        // SQL expressions building the text of a statement from the row `row`.
+        let values = |row: &str| columns.iter()
+            .map(|c| format!("quote({}.{})", row, c))
+            .collect::<Vec<_>>()
+            .join(" || ', ' || ");
+        let insert = |row: &str| format!("'INSERT INTO main.{} ({}) VALUES (' || {} || ')'", table, column_list, values(row));
+        let update = |row: &str, key_row: &str| format!("'UPDATE main.{} SET ' || {} || ' WHERE id = ' || quote({}.id)",
+            table,
+            columns.iter().map(|c| format!("'{} = ' || quote({}.{})", c, row, c)).collect::<Vec<_>>().join(" || ', ' || "),
+            key_row);
+        let delete = |row: &str| format!("'DELETE FROM main.{} WHERE id = ' || quote({}.id)", table, row);
+
+        for (event, undo, redo) in [
+            ("INSERT", delete("new"), insert("new")),
+            ("UPDATE", update("old", "new"), update("new", "old")),
+            ("DELETE", insert("old"), delete("old")),
+        ] {
+            db.execute_batch(&format!("CREATE TEMP TRIGGER journal_{table}_{name} AFTER {event} ON main.{table}
+            BEGIN
+                INSERT INTO journal_changes(journal_id, undo_sql, redo_sql) VALUES ({journal_id}, {undo}, {redo});
+            END",
+            name = event.to_lowercase()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove the triggers created by [`install_triggers`].
+fn drop_triggers(db: &Connection) -> AppResult<()> {
+    for &table in JOURNALED_TABLES {
+        for event in ["insert", "update", "delete"] {
+            db.execute_batch(&format!("DROP TRIGGER IF EXISTS temp.journal_{}_{}", table, event))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::*;
+    use crate::migrations::test_db;
+
+    fn task_texts(db: &SqliteStore) -> Vec<String> {
+        list_tasks(db).unwrap().into_iter().map(|task| task.task).collect()
+    }
+
+    #[test]
+    fn undo_and_redo_push() {
+        let mut db = test_db();
+        record(&mut db, "add a", |db| push_task(db, "a".into())).unwrap();
+        record(&mut db, "add b", |db| push_task(db, "b".into())).unwrap();
+        assert_eq!(undo(db.connection_mut()).unwrap(), "add b");
+        assert_eq!(task_texts(&db), vec!["a"]);
+        assert_eq!(redo(db.connection_mut()).unwrap(), "add b");
+        assert_eq!(task_texts(&db), vec!["a", "b"]);
+        assert!(matches!(redo(db.connection_mut()), Err(AppError::Journal(JournalError::NothingToRedo))));
+    }
+
+    #[test]
+    fn undo_keeps_time_spent() {
+        let mut db = test_db();
+        let a = record(&mut db, "add a", |db| push_task(db, "a".into())).unwrap();
+        track_time(&mut db, 100).unwrap();
+        let b = record(&mut db, "add b", |db| push_task(db, "b".into())).unwrap();
+        track_time(&mut db, 160).unwrap();
+        undo(db.connection_mut()).unwrap();
+        track_time(&mut db, 200).unwrap();
+        let total = |name: &str, task_id: Option<TaskId>, seconds: i64| TimeTotal { name: name.into(), task_id, seconds };
+        assert_eq!(time_totals(&db, TimeGrouping::Task, None, None, 300).unwrap(),
+            vec![total("a", Some(a.id), 160), total("(deleted tasks)", None, 40)]);
+
+        redo(db.connection_mut()).unwrap();
+        track_time(&mut db, 300).unwrap();
+        assert_eq!(time_totals(&db, TimeGrouping::Task, None, None, 360).unwrap(),
+            vec![total("a", Some(a.id), 160), total("b", Some(b.id), 60), total("(deleted tasks)", None, 40)]);
+    }
+
+    #[test]
+    fn undo_clear_across_stacks() {
+        let mut db = test_db();
+        record(&mut db, "add a", |db| push_task(db, "a".into())).unwrap();
+        record(&mut db, "newstack other", |db| new_stack(db, "other".into())).unwrap();
+        record(&mut db, "switchto other", |db| switch_to_stack(db, "other".into())).unwrap();
+        record(&mut db, "clearall", clear_all_tasks).unwrap();
+        assert!(task_texts(&db).is_empty());
+        undo(db.connection_mut()).unwrap();
+        undo(db.connection_mut()).unwrap();
+        assert_eq!(get_current_stack_name(&db).unwrap(), "default");
+        assert_eq!(task_texts(&db), vec!["a"]);
+    }
+
+    #[test]
+    fn recording_discards_undone_commands() {
+        let mut db = test_db();
+        record(&mut db, "add a", |db| push_task(db, "a".into())).unwrap();
+        undo(db.connection_mut()).unwrap();
+        record(&mut db, "add b", |db| push_task(db, "b".into())).unwrap();
+        assert!(matches!(redo(db.connection_mut()), Err(AppError::Journal(JournalError::NothingToRedo))));
+        assert_eq!(undo(db.connection_mut()).unwrap(), "add b");
+        assert!(matches!(undo(db.connection_mut()), Err(AppError::Journal(JournalError::NothingToUndo))));
+    }
+}
@@ -0,0 +1,522 @@
+//! Stack-based task tracking.
+//!
+//! Tasks live on named stacks in a SQLite database. New tasks go on top of the
+//! current stack, and are popped off when they're done; any task can get a
+//! sub-stack of its own, which becomes the current context when dived into.
+//!
+//! [`YakStack`] is a handle on a database, and the only way in:
+//!
+//! ```no_run
+//! # fn main() -> yakstack::AppResult<()> {
+//! let mut yak = yakstack::YakStack::open(yakstack::paths::default_db_path()?)?;
+//! yak.push("shave the yak")?;
+//! for task in yak.tasks()? {
+//!     println!("{} {}", task.short_id, task.task);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod errors;
+pub mod export;
+pub mod migrations;
+pub mod paths;
+pub mod store;
+pub mod timespec;
+pub mod types;
+mod commands;
+mod journal;
+#[cfg(unix)]
+mod daemon;
+
+pub use errors::{AppError, AppResult};
+pub use types::*;
+
+use commands::*;
+use export::{Document, ImportMode};
+use store::SqliteStore;
+
+use std::path::{Path, PathBuf};
+
+/// An open yakstack database.
+pub struct YakStack {
+    store: SqliteStore,
+    /// Where the database is, or `None` if it's in memory.
+    path: Option<PathBuf>,
+    /// Reminders to schedule once the recorded command that set them is committed.
+    unscheduled: Vec<String>,
+}
+
+impl YakStack {
+    /// Open or create the database at `path`, bringing its schema up to date.
+    pub fn open(path: impl AsRef<Path>) -> AppResult<YakStack> {
+        let mut yak = YakStack::open_unmigrated(path)?;
+        yak.migrate()?;
+        Ok(yak)
+    }
+
+    /// Open or create the database at `path` without migrating it.
+    ///
+    /// Only [`YakStack::schema_version`] and [`YakStack::migrate`] can be
+    /// relied on until the database has been migrated.
+    pub fn open_unmigrated(path: impl AsRef<Path>) -> AppResult<YakStack> {
+        let path = path.as_ref();
+        Ok(YakStack { store: SqliteStore::open(path)?, path: Some(path.to_path_buf()), unscheduled: Vec::new() })
+    }
+
+    /// Create a fully migrated database in memory, which disappears when dropped.
+    ///
+    /// Reminders need a database file, so they can't be set.
+    pub fn open_in_memory() -> AppResult<YakStack> {
+        let mut yak = YakStack { store: SqliteStore::open_in_memory()?, path: None, unscheduled: Vec::new() };
+        yak.migrate()?;
+        Ok(yak)
+    }
+
+    /// Get where the database is, or `None` if it's in memory.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Get the database's schema version.
+    pub fn schema_version(&self) -> AppResult<u32> {
+        migrations::schema_version(self.store.connection())
+    }
+
+    /// Bring the database's schema up to date, returning the versions applied.
+    pub fn migrate(&mut self) -> AppResult<Vec<u32>> {
+        migrations::migrate(self.store.connection_mut())
+    }
+
+    /// Spread out the order of the tasks in every stack and sub-stack, returning
+    /// the number of tasks that changed. Orders are also renormalized
+    /// automatically as they get close together.
+    pub fn compact(&mut self) -> AppResult<usize> {
+        compact_orders(&mut self.store)
+    }
+
+    /// Run `f` as the command `command`, so that [`YakStack::undo`] can undo it.
+    ///
+    /// The command runs in a transaction: if `f` fails, none of its changes
+    /// are kept, and there's nothing to undo. Afterwards, time tracking moves
+    /// on to whichever task is now on top.
+    pub fn record<T>(&mut self, command: &str, f: impl FnOnce(&mut YakStack) -> AppResult<T>) -> AppResult<T> {
+        let result = self.transaction(|yak| {
+            let journal_id = journal::begin(yak.store.connection(), command)?;
+            let result = f(yak)?;
+            journal::finish(yak.store.connection(), journal_id)?;
+            yak.track_time()?;
+            Ok(result)
+        });
+        // Processes waiting for reminders wouldn't see them before the commit.
+        let unscheduled = std::mem::take(&mut self.unscheduled);
+        if result.is_ok() {
+            self.schedule_reminders(unscheduled)?;
+        }
+        result
+    }
+
+    /// Run `f` in a transaction, rolling back all its changes if it fails.
+    fn transaction<T>(&mut self, f: impl FnOnce(&mut YakStack) -> AppResult<T>) -> AppResult<T> {
+        // Take the write lock up front, so that reads in `f` can't be invalidated.
+        self.store.connection().execute_batch("BEGIN IMMEDIATE")?;
+        let result = f(self).and_then(|value| {
+            self.store.connection().execute_batch("COMMIT")?;
+            Ok(value)
+        });
+        if result.is_err() {
+            // Keep the error that made the command fail rather than any from rolling back.
+            let _ = self.store.connection().execute_batch("ROLLBACK");
+        }
+        result
+    }
+
+    /// Undo the most recent recorded command that hasn't been undone, returning it.
+    pub fn undo(&mut self) -> AppResult<String> {
+        let command = journal::undo(self.store.connection_mut())?;
+        self.track_time()?;
+        Ok(command)
+    }
+
+    /// Redo the least recently undone command, returning it.
+    pub fn redo(&mut self) -> AppResult<String> {
+        let command = journal::redo(self.store.connection_mut())?;
+        self.track_time()?;
+        Ok(command)
+    }
+
+    /// Time whichever task is on top of the current (sub-)stack from now on.
+    ///
+    /// [`YakStack::record`], undo, redo and switching stacks through a binding
+    /// do this themselves; other changes are only timed once this is called.
+    pub fn track_time(&mut self) -> AppResult<()> {
+        track_time(&mut self.store, now())
+    }
+
+    /// Stop timing tasks until [`YakStack::resume`], returning the task that was being timed.
+    pub fn pause(&mut self) -> AppResult<Option<Task>> {
+        pause_timer(&mut self.store, now())
+    }
+
+    /// Start timing tasks again after [`YakStack::pause`], returning the task now being timed.
+    pub fn resume(&mut self) -> AppResult<Option<Task>> {
+        resume_timer(&mut self.store, now())
+    }
+
+    /// Add up the time tasks spent on top of the current stack between `since` and `until`.
+    pub fn time_totals(&self, grouping: TimeGrouping, since: Option<Timestamp>, until: Option<Timestamp>) -> AppResult<Vec<TimeTotal>> {
+        time_totals(&self.store, grouping, since, until, now())
+    }
+
+    /// Get the current stack and sub-stack.
+    pub fn context(&self) -> AppResult<Context> {
+        get_current_context(&self.store)
+    }
+
+    /// Get the name of the current stack followed by the tasks enclosing the current sub-stack.
+    pub fn context_path(&self) -> AppResult<Vec<String>> {
+        let mut path = vec![get_current_stack_name(&self.store)?];
+        path.extend(get_current_context_path(&self.store)?);
+        Ok(path)
+    }
+
+    /// Push `task` onto the top of the current (sub-)stack.
+    pub fn push(&mut self, task: impl Into<String>) -> AppResult<Task> {
+        push_task(&mut self.store, task.into())
+    }
+
+    /// Put `task` at the bottom of the current (sub-)stack.
+    pub fn push_back(&mut self, task: impl Into<String>) -> AppResult<Task> {
+        pushback_task(&mut self.store, task.into())
+    }
+
+    /// Insert `task` right above the task `after`, in whichever (sub-)stack `after` is in.
+    pub fn insert_after(&mut self, after: TaskRef, task: impl Into<String>) -> AppResult<Task> {
+        insert_after(&mut self.store, after, task.into())
+    }
+
+    /// Add `tags` to the open task `task`. Tags may be written with a leading `#`.
+    pub fn tag(&mut self, task: TaskRef, tags: &[String]) -> AppResult<Task> {
+        tag_task(&mut self.store, task, tags)
+    }
+
+    /// Replace the text of the open task `task`, keeping its reminders, notes and place.
+    pub fn edit(&mut self, task: TaskRef, text: String) -> AppResult<Task> {
+        edit_task(&mut self.store, task, text)
+    }
+
+    /// Apply `substitution` to every open task on `stack`, or the current
+    /// stack if it's `None`. Returns the tasks that changed.
+    pub fn substitute(&mut self, stack: Option<&str>, substitution: &Substitution) -> AppResult<Vec<Task>> {
+        let stack_id = match stack {
+            Some(name) => stack_name_to_id(&self.store, name)?,
+            None => get_current_stack_id(&self.store)?,
+        };
+        substitute_tasks(&mut self.store, stack_id, substitution)
+    }
+
+    /// Add a note to the open task `task`. Surrounding whitespace is trimmed.
+    pub fn note(&mut self, task: TaskRef, note: String) -> AppResult<Task> {
+        add_note(&mut self.store, task, note)
+    }
+
+    /// Get a task with its notes and pending reminders. Finished tasks can be shown by ID.
+    pub fn show(&self, task: TaskRef) -> AppResult<TaskDetails> {
+        show_task(&self.store, task)
+    }
+
+    /// Pop the top task off the current (sub-)stack, marking it done.
+    ///
+    /// Returns `None` if there are no tasks. Popping the last task of a
+    /// sub-stack returns to the enclosing stack.
+    pub fn pop(&mut self) -> AppResult<Option<Task>> {
+        pop_task(&mut self.store)
+    }
+
+    /// Move the top task, with its sub-tasks, to the top of the stack `stack`.
+    pub fn pop_to(&mut self, stack: &str) -> AppResult<Option<Task>> {
+        pop_to(&mut self.store, stack.into())
+    }
+
+    /// Move the tasks in `range`, with their sub-tasks and reminders, to `position` on the stack `stack`.
+    ///
+    /// Returns the moved tasks, bottom first.
+    pub fn move_tasks(&mut self, range: TaskRange, stack: &str, position: MovePosition) -> AppResult<Vec<Task>> {
+        move_tasks(&mut self.store, range, stack.into(), position)
+    }
+
+    /// Get the top task without popping it.
+    pub fn peek(&self) -> AppResult<Task> {
+        peek_task(&self.store)
+    }
+
+    /// Bring the task `depth` places below the top up to the top, the top task being 0.
+    pub fn roll(&mut self, depth: TaskIndex) -> AppResult<Task> {
+        roll_task(&mut self.store, depth)
+    }
+
+    /// Send the top task to the bottom of the stack.
+    pub fn bury(&mut self) -> AppResult<Task> {
+        bury_task(&mut self.store)
+    }
+
+    /// Push a copy of the task `depth` places below the top, the top task being 0, with its tags.
+    pub fn dup(&mut self, depth: TaskIndex) -> AppResult<Task> {
+        dup_task(&mut self.store, depth)
+    }
+
+    /// Swap the positions of two tasks in the same (sub-)stack.
+    pub fn swap(&mut self, task1: TaskRef, task2: TaskRef) -> AppResult<()> {
+        swap_tasks(&mut self.store, task1, task2)
+    }
+
+    /// Remove a task and its sub-tasks without completing them, marking them abandoned.
+    pub fn kill(&mut self, task: TaskRef) -> AppResult<Task> {
+        kill_task(&mut self.store, task)
+    }
+
+    /// Abandon every task in the current (sub-)stack.
+    pub fn clear(&mut self) -> AppResult<()> {
+        clear_tasks(&mut self.store)
+    }
+
+    /// Abandon every open task on every stack.
+    pub fn clear_all(&mut self) -> AppResult<()> {
+        clear_all_tasks(&mut self.store)
+    }
+
+    /// Make the sub-stack of the top task the current context, returning the task.
+    pub fn dive(&mut self) -> AppResult<Task> {
+        dive(&mut self.store)
+    }
+
+    /// Return from the current sub-stack to the enclosing stack, returning the sub-stack's task.
+    pub fn surface(&mut self) -> AppResult<Task> {
+        surface(&mut self.store)
+    }
+
+    /// List the tasks in the current (sub-)stack, bottom first.
+    pub fn tasks(&self) -> AppResult<Vec<Task>> {
+        list_tasks(&self.store)
+    }
+
+    /// List the open tasks on every stack, sub-stacks included, that pass `filter`.
+    pub fn find_tasks(&self, filter: &TagFilter) -> AppResult<Vec<Task>> {
+        find_tasks(&self.store, filter)
+    }
+
+    /// List every open task on the current stack, with each task's sub-tasks following it.
+    pub fn task_tree(&self) -> AppResult<Vec<TreeTask>> {
+        list_task_tree(&self.store)
+    }
+
+    /// List tasks on `stack`, or all stacks if it's `None`, finished between `since` and `until`, most recent first.
+    pub fn finished_tasks(&self, stack: Option<&str>, since: Option<Timestamp>, until: Option<Timestamp>) -> AppResult<Vec<Task>> {
+        let stack_id = stack.map(|name| stack_name_to_id(&self.store, name)).transpose()?;
+        list_finished_tasks(&self.store, stack_id, since, until)
+    }
+
+    /// Get the current stack.
+    pub fn current_stack(&self) -> AppResult<Stack> {
+        get_stack(&self.store, &get_current_stack_name(&self.store)?)
+    }
+
+    /// List all stacks.
+    pub fn stacks(&self) -> AppResult<Vec<Stack>> {
+        list_stacks(&self.store)
+    }
+
+    /// Create an empty stack called `name`.
+    pub fn new_stack(&mut self, name: &str) -> AppResult<Stack> {
+        new_stack(&mut self.store, name.into())
+    }
+
+    /// Make `name` the current stack.
+    pub fn switch_to(&mut self, name: &str) -> AppResult<()> {
+        switch_to_stack(&mut self.store, name.into())
+    }
+
+    /// Delete the stack `name`, with all its tasks and their history.
+    pub fn drop_stack(&mut self, name: &str) -> AppResult<()> {
+        drop_stack(&mut self.store, name.into())
+    }
+
+    /// Rename the stack `old_name` to `new_name`.
+    pub fn rename_stack(&mut self, old_name: &str, new_name: &str) -> AppResult<Stack> {
+        rename_stack(&mut self.store, old_name, new_name.into())
+    }
+
+    /// Copy the open tasks on `source`, and their reminders, to a new stack called `destination`.
+    pub fn copy_stack(&mut self, source: &str, destination: &str) -> AppResult<Stack> {
+        let (stack, reminder_ids) = copy_stack(&mut self.store, source, destination.into())?;
+        self.schedule_reminders(reminder_ids)?;
+        Ok(stack)
+    }
+
+    /// Move every task on `source` onto `destination`, keeping their order, and drop `source`.
+    ///
+    /// The default stack is left empty instead of being dropped.
+    pub fn merge_stack(&mut self, source: &str, destination: &str, position: MergePosition) -> AppResult<()> {
+        merge_stack(&mut self.store, source, destination, position)
+    }
+
+    /// Bind `stack`, or the current stack if it's `None`, to the directory `dir`.
+    ///
+    /// From then on, [`YakStack::use_binding`] switches to the stack anywhere
+    /// inside `dir`. Paths are compared as they are, so `dir` should be canonical.
+    pub fn bind(&mut self, stack: Option<&str>, dir: &Path) -> AppResult<Binding> {
+        let stack = match stack {
+            Some(stack) => stack.to_string(),
+            None => get_current_stack_name(&self.store)?,
+        };
+        bind_stack(&mut self.store, stack, dir)
+    }
+
+    /// Remove the binding that applies in `dir`, returning it.
+    pub fn unbind(&mut self, dir: &Path) -> AppResult<Binding> {
+        unbind(&mut self.store, dir)
+    }
+
+    /// Get the binding that applies in `dir`: the one for `dir` itself, or else for its nearest ancestor.
+    pub fn binding(&self, dir: &Path) -> AppResult<Option<Binding>> {
+        find_binding(&self.store, dir)
+    }
+
+    /// Switch to the stack bound to `dir`, if any, returning the binding that applies.
+    pub fn use_binding(&mut self, dir: &Path) -> AppResult<Option<Binding>> {
+        let binding = use_binding(&mut self.store, dir)?;
+        self.track_time()?;
+        Ok(binding)
+    }
+
+    /// Export the stack `stack`, or every stack if it's `None`.
+    pub fn export(&self, stack: Option<&str>) -> AppResult<Document> {
+        let stack_id = stack.map(|name| stack_name_to_id(&self.store, name)).transpose()?;
+        export::export(&self.store, stack_id)
+    }
+
+    /// Import the stacks in `document`, creating any that don't exist.
+    pub fn import(&mut self, document: &Document, mode: ImportMode) -> AppResult<()> {
+        let reminder_ids = export::import(&mut self.store, document, mode)?;
+        self.schedule_reminders(reminder_ids)
+    }
+
+    /// Schedule newly created or moved reminders, once any command being recorded is committed.
+    fn schedule_reminders(&mut self, reminder_ids: Vec<String>) -> AppResult<()> {
+        if !self.store.connection().is_autocommit() {
+            self.unscheduled.extend(reminder_ids);
+            return Ok(());
+        }
+        // Reminders in an in-memory database never fire anyway.
+        if let Some(path) = &self.path {
+            for reminder_id in reminder_ids {
+                schedule_reminder(path, &reminder_id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remind the user of `task` at the time `time_spec`, parsed with [`timespec::parse`].
+    pub fn remind(&mut self, task: TaskRef, time_spec: &str) -> AppResult<Reminder> {
+        self.require_path()?;
+        let reminder = remind_me(&mut self.store, task, time_spec.into())?;
+        self.schedule_reminders(vec![reminder.id.clone()])?;
+        Ok(reminder)
+    }
+
+    /// List pending reminders on all stacks, soonest first.
+    pub fn reminders(&self) -> AppResult<Vec<Reminder>> {
+        list_reminders(&self.store)
+    }
+
+    /// Cancel the pending reminder whose ID is or starts with `id_prefix`.
+    pub fn cancel_reminder(&mut self, id_prefix: &str) -> AppResult<()> {
+        cancel_reminder(&mut self.store, id_prefix)
+    }
+
+    /// Put a pending reminder off by the duration `delay`, like `10m`.
+    pub fn snooze_reminder(&mut self, id_prefix: &str, delay: &str) -> AppResult<()> {
+        self.require_path()?;
+        let reminder_id = snooze_reminder(&mut self.store, id_prefix, delay)?;
+        self.schedule_reminders(vec![reminder_id])
+    }
+
+    /// Make a pending reminder fire at the time `time_spec` instead.
+    pub fn reschedule_reminder(&mut self, id_prefix: &str, time_spec: &str) -> AppResult<()> {
+        self.require_path()?;
+        let reminder_id = reschedule_reminder(&mut self.store, id_prefix, time_spec)?;
+        self.schedule_reminders(vec![reminder_id])
+    }
+
+    /// Wait for the reminder `reminder_id` to come due, then show it.
+    pub fn trigger_reminder(self, reminder_id: &str) -> AppResult<()> {
+        let path = self.require_path()?;
+        trigger_reminder(path, self.store, reminder_id.into())
+    }
+
+    /// Show reminders as they come due, forever, as `yakstack daemon` does.
+    #[cfg(unix)]
+    pub fn run_daemon(self) -> AppResult<()> {
+        let path = self.require_path()?;
+        daemon::run(&path, self.store)
+    }
+
+    fn require_path(&self) -> AppResult<PathBuf> {
+        self.path.clone().ok_or_else(|| AppError::Environment("reminders need a database file".into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_api_round_trip() {
+        let mut yak = YakStack::open_in_memory().unwrap();
+        let pushed = yak.record("add shave yak", |yak| yak.push("shave yak")).unwrap();
+        assert_eq!(pushed.task, "shave yak");
+        assert_eq!(pushed.stack, "default");
+        assert_eq!(yak.dive().unwrap().id, pushed.id);
+        yak.push("find razor").unwrap();
+        assert_eq!(yak.context_path().unwrap(), vec!["default", "shave yak"]);
+        assert_eq!(yak.pop().unwrap().unwrap().status, TaskStatus::Done);
+        assert_eq!(yak.context().unwrap().parent_id, None);
+        assert!(matches!(yak.remind(TaskRef::Id(pushed.id), "1h"), Err(AppError::Environment(_))));
+
+        assert_eq!(yak.undo().unwrap(), "add shave yak");
+        assert!(yak.tasks().unwrap().is_empty());
+        assert_eq!(yak.new_stack("other").unwrap().tasks, 0);
+    }
+
+    #[test]
+    fn recorded_commands_move_the_timer() {
+        let mut yak = YakStack::open_in_memory().unwrap();
+        let a = yak.record("add a", |yak| yak.push("a")).unwrap();
+        assert_eq!(yak.pause().unwrap().unwrap().id, a.id);
+        assert!(matches!(yak.pause(), Err(AppError::Time(_))));
+        assert_eq!(yak.resume().unwrap().unwrap().id, a.id);
+        let b = yak.record("add b", |yak| yak.push("b")).unwrap();
+        assert_eq!(yak.pause().unwrap().unwrap().id, b.id);
+        yak.resume().unwrap();
+        // Undoing puts the task below back on top, and back on the clock.
+        yak.undo().unwrap();
+        assert_eq!(yak.pause().unwrap().unwrap().id, a.id);
+    }
+
+    #[test]
+    fn failed_commands_leave_nothing_behind() {
+        let mut yak = YakStack::open_in_memory().unwrap();
+        yak.record("add a", |yak| yak.push("a")).unwrap();
+        let result = yak.record("add b and fail", |yak| {
+            yak.push("b")?;
+            yak.switch_to("nowhere")
+        });
+        assert!(matches!(result, Err(AppError::Stack(_))));
+        let tasks: Vec<String> = yak.tasks().unwrap().into_iter().map(|task| task.task).collect();
+        assert_eq!(tasks, vec!["a"]);
+        assert_eq!(yak.undo().unwrap(), "add a");
+        assert!(matches!(yak.undo(), Err(AppError::Journal(_))));
+        // The database is usable again afterwards.
+        assert_eq!(yak.redo().unwrap(), "add a");
+    }
+}
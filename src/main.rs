@@ -2,25 +2,29 @@ use std::error::Error as StdError;
 use std::env;
 use std::process;
 use std::ffi::OsString;
-use std::time::Duration;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 
-use rusqlite::Connection;
-use rusqlite::params;
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap::error::ErrorKind;
+use chrono::{NaiveDate, Utc};
+use yakstack::{YakStack, migrations, paths, timespec};
+use yakstack::export::{Document, DocumentFormat, ImportMode};
+use yakstack::types::*;
+use yakstack::errors::{AppError, TaskError, CommandError};
 
-mod commands;
-mod types;
-mod errors;
+mod output;
 
-use types::*;
-use commands::*;
-use errors::{AppResult, TaskError, CommandError};
+use output::OutputFormat;
 
 fn main() {
-    match app_main() {
+    // Errors from before the command line is parsed are always plain text.
+    let mut format = OutputFormat::Plain;
+    match app_main(&mut format) {
         Ok(()) => {},
         Err(e) => {
-            eprintln!("Error: {}", e);
+            output::print_error(format, e.as_ref());
             process::exit(1);
         }
     }
@@ -30,162 +34,806 @@ fn main() {
 static COMMANDS: &[&str] = &[
     "add",
     "backpush",
+    "bind",
+    "bury",
     "clear",
     "clearall",
+    "copystack",
+    "daemon",
+    "db",
+    "dive",
     "dropstack",
+    "dup",
+    "edit",
+    "export",
+    "import",
     "insertafter",
     "kill",
     "liststacks",
+    "log",
     "ls",
+    "mergestack",
+    "move",
     "newstack",
+    "note",
+    "over",
+    "pause",
+    "peek",
     "pop",
+    "redo",
+    "reminders",
     "remindme",
+    "renamestack",
+    "resume",
+    "roll",
+    "rot",
+    "show",
+    "surface",
     "swap",
     "switchto",
+    "time",
     "triggerreminder",
+    "unbind",
+    "undo",
 ];
 
 #[derive(Parser)]
 #[command(version = "0.4.0", about = "Stack-based task tracker", long_about = None)]
+#[command(after_help = "Tasks are referred to by their index in `ls`, counting from 0 at the bottom, or by their ID, like '#a3f'. \
+IDs don't change when other tasks are added or removed. Quote IDs in shells that treat # as a comment.")]
 struct Cli {
+    /// Path to the database. Defaults to $XDG_DATA_HOME/yakstack/yakstack.db.
+    #[arg(long, global = true, env = "YAKSTACK_DB", value_name = "PATH")]
+    db: Option<PathBuf>,
+    /// Output format for commands that report tasks or stacks, or document format for export and import.
+    #[arg(long, global = true, value_enum, default_value_t)]
+    format: Format,
     #[command(subcommand)]
     command: Command
 }
 
+/// Formats `--format` accepts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// Human-readable text. Export and import use JSON.
+    #[default]
+    Plain,
+    /// JSON documents.
+    Json,
+    /// Tab-separated values with a header line.
+    Tsv,
+    /// Markdown checklists. Only for export and import.
+    Markdown,
+    /// todo.txt lines, with stacks as +projects. Only for export and import.
+    Todotxt,
+    /// iCalendar to-dos, with reminders as alarms. Only for export.
+    Ics,
+}
+
+impl Format {
+    /// Get the format to report tasks, stacks and errors in, if this is one.
+    fn output_format(self) -> Option<OutputFormat> {
+        match self {
+            Format::Plain => Some(OutputFormat::Plain),
+            Format::Json => Some(OutputFormat::Json),
+            Format::Tsv => Some(OutputFormat::Tsv),
+            Format::Markdown | Format::Todotxt | Format::Ics => None,
+        }
+    }
+
+    /// Get the format to export or import documents in, if this is one.
+    fn document_format(self) -> Option<DocumentFormat> {
+        match self {
+            Format::Plain | Format::Json => Some(DocumentFormat::Json),
+            Format::Markdown => Some(DocumentFormat::Markdown),
+            Format::Todotxt => Some(DocumentFormat::Todotxt),
+            Format::Ics => Some(DocumentFormat::Ics),
+            Format::Tsv => None,
+        }
+    }
+}
+
 #[derive(Subcommand)]
 enum Command {
     /// Push a task onto the stack
     Add {
-        /// Task text to use
+        /// Task text to use. Words like '#ci' in it are tags.
         task: String,
+        /// Tag the task. Can be given more than once.
+        #[arg(short, long = "tag", value_name = "TAG", value_parser = parse_tag)]
+        tags: Vec<String>,
     },
     /// Push a task onto the bottom of the stack.
     Backpush {
-        /// Task description
+        /// Task description. Words like '#ci' in it are tags.
         task: String,
+        /// Tag the task. Can be given more than once.
+        #[arg(short, long = "tag", value_name = "TAG", value_parser = parse_tag)]
+        tags: Vec<String>,
     },
+    /// Bind a stack to this directory's git repository, or to this directory outside one.
+    /// Commands run anywhere inside it then switch to the stack.
+    Bind {
+        /// Stack to bind. Defaults to the current stack.
+        stack: Option<String>,
+        /// Bind to this directory even inside a git repository.
+        #[arg(long)]
+        here: bool,
+    },
+    /// Send the top task to the bottom of the stack.
+    Bury,
     /// Clear all tasks on the current stack.
     Clear,
     /// Wipe all stacks clean.
     Clearall,
+    /// Copy the open tasks on a stack, and their reminders, to a new stack.
+    Copystack {
+        source: String,
+        /// Name of the new stack.
+        destination: String,
+    },
+    /// Fire reminders as they come due. Runs until killed.
+    #[cfg(unix)]
+    Daemon,
+    /// Database maintenance.
+    Db {
+        #[command(subcommand)]
+        command: DbCommand,
+    },
+    /// Work on the sub-stack of the top task.
+    Dive,
+    /// Push a copy of the top task, with its tags. Its sub-tasks and reminders aren't copied.
+    Dup,
     /// Delete a stack and all its items.
     Dropstack {
         stack: String,
     },
+    /// Change a task's text, keeping its reminders, notes and place on the stack.
+    Edit {
+        /// Index or ID of the task.
+        #[arg(required_unless_present = "regex")]
+        task: Option<TaskRef>,
+        /// New text. Defaults to editing the current text in $VISUAL or $EDITOR.
+        text: Option<String>,
+        /// Rewrite every open task on the stack with a sed-style substitution, like 's/old/new/g'.
+        /// '&' in the replacement is the match and '\1' a group; 'g' replaces every match and 'i' ignores case.
+        #[arg(long, value_name = "s/PATTERN/REPLACEMENT/", conflicts_with_all = ["task", "text"])]
+        regex: Option<Substitution>,
+        /// Stack to rewrite with --regex. Defaults to the current stack.
+        #[arg(long, requires = "regex")]
+        stack: Option<String>,
+    },
+    /// Print stacks as a document, for backing up, moving or sharing them. JSON documents hold everything.
+    Export {
+        /// Only export this stack.
+        #[arg(long)]
+        stack: Option<String>,
+    },
+    /// Import stacks from a document written by export.
+    Import {
+        /// File to read the document from, or '-' for standard input.
+        file: PathBuf,
+        /// Put imported tasks on top of existing stacks' tasks. The default.
+        #[arg(long, conflicts_with = "replace")]
+        merge: bool,
+        /// Replace the tasks and history of existing stacks, and switch to the document's current stack.
+        #[arg(long)]
+        replace: bool,
+    },
     /// Insert a task after another task in the stack.
     Insertafter {
         task: String,
-        after: TaskIndex,
+        /// Index or ID of the task to insert above.
+        after: TaskRef,
+        /// Tag the task. Can be given more than once.
+        #[arg(short, long = "tag", value_name = "TAG", value_parser = parse_tag)]
+        tags: Vec<String>,
     },
     /// Delete a task.
     Kill {
-        task: TaskIndex,
+        /// Index or ID of the task to delete.
+        task: TaskRef,
     },
     /// List all stacks.
     Liststacks,
+    /// List finished tasks, most recent first.
+    Log {
+        /// Only show tasks from this stack. Defaults to the current stack.
+        #[arg(long, conflicts_with = "all_stacks")]
+        stack: Option<String>,
+        /// Show tasks from every stack.
+        #[arg(long)]
+        all_stacks: bool,
+        /// Only show tasks finished on or after this date (YYYY-MM-DD).
+        #[arg(long, value_parser = parse_date)]
+        since: Option<NaiveDate>,
+        /// Only show tasks finished on or before this date (YYYY-MM-DD).
+        #[arg(long, value_parser = parse_date)]
+        until: Option<NaiveDate>,
+    },
     /// List all tasks on the current stack.
-    Ls,
+    Ls {
+        /// Show the whole stack, including sub-stacks.
+        #[arg(long, conflicts_with_all = ["tags", "not_tags", "all_stacks"])]
+        tree: bool,
+        /// Only show tasks with this tag. Can be given more than once.
+        #[arg(long = "tag", value_name = "TAG", value_parser = parse_tag)]
+        tags: Vec<String>,
+        /// Only show tasks without this tag. Can be given more than once.
+        #[arg(long = "not-tag", value_name = "TAG", value_parser = parse_tag)]
+        not_tags: Vec<String>,
+        /// Show matching tasks on every stack, sub-stacks included.
+        #[arg(long)]
+        all_stacks: bool,
+    },
+    /// Move every task on a stack onto another, keeping their order, and delete the first stack.
+    /// The default stack is emptied instead of deleted.
+    #[command(override_usage = "yakstack mergestack [OPTIONS] <SOURCE> [into] <DESTINATION>")]
+    Mergestack {
+        source: String,
+        /// Stack to move the tasks onto. It may follow 'into', as in 'mergestack old into default'.
+        #[arg(num_args = 1..=2, required = true, value_name = "DESTINATION")]
+        destination: Vec<String>,
+        /// Put the tasks on top of the destination's own tasks. The default.
+        #[arg(long, conflicts_with = "below")]
+        on_top: bool,
+        /// Put the tasks underneath the destination's own tasks.
+        #[arg(long)]
+        below: bool,
+    },
+    /// Move a task, or a run of tasks like '2..4', to another stack. Sub-tasks and reminders go with them.
+    Move {
+        /// Index or ID of the task to move, or two of them with '..' between to move them and everything in between.
+        tasks: TaskRange,
+        /// Stack to move the tasks to.
+        stack: String,
+        /// Put the tasks on top of the stack. The default.
+        #[arg(long, group = "position")]
+        top: bool,
+        /// Put the tasks at the bottom of the stack.
+        #[arg(long, group = "position")]
+        bottom: bool,
+        /// Put the tasks right above this task. Indices count from the bottom of the destination stack.
+        #[arg(long, group = "position", value_name = "TASK")]
+        after: Option<TaskRef>,
+    },
     /// Create a new stack
     Newstack {
         /// Name of the new stack. Must not be the same as an existing stack's name!
         name: String,
     },
+    /// Add a note to a task, like an error message or a link.
+    Note {
+        /// Index or ID of the task.
+        task: TaskRef,
+        /// Text of the note.
+        #[arg(required_unless_present = "edit")]
+        text: Option<String>,
+        /// Write the note in $VISUAL or $EDITOR instead.
+        #[arg(long, conflicts_with = "text")]
+        edit: bool,
+    },
+    /// Push a copy of the task below the top one.
+    Over,
+    /// Stop timing the top task, as for a break.
+    Pause,
+    /// Show the top task without popping it.
+    Peek,
     /// Pop a task from the top of the stack
     Pop {
         /// Name of the stack to push onto
         name: Option<String>,
     },
+    /// Redo the last undone command.
+    Redo,
+    /// List pending reminders, or manage one.
+    Reminders {
+        #[command(subcommand)]
+        command: Option<RemindersCommand>,
+    },
     /// Create a task reminder at some future point in time.
     Remindme {
-        /// Task to remind me of. If the task is completed, the reminder will not trigger.
-        task: TaskIndex,
-        /// How long to wait. Specified as ([1-9][0-9]*h)?([1-9][0-9]*m)?([1-9][0-9]*s)?
+        /// Index or ID of the task to remind me of. If the task is completed, the reminder will not trigger.
+        task: TaskRef,
+        /// When to remind me: a duration like '1h30m', a time like '16:00' or '9am', a day like 'tomorrow',
+        /// 'monday' or '2026-12-24', or a day and a time like 'tomorrow 9am'. Times are local.
         delay: String,
     },
+    /// Give a stack a new name.
+    Renamestack {
+        old_name: String,
+        /// Must not be the same as an existing stack's name.
+        new_name: String,
+    },
+    /// Start timing the top task again after a pause.
+    Resume,
+    /// Bring a task up to the top of the stack.
+    Roll {
+        /// How far down the task is, counting from 0 at the top. Unlike indices elsewhere!
+        depth: TaskIndex,
+    },
+    /// Bring the third task from the top up to the top.
+    Rot,
+    /// Show a task with its notes and reminders. Finished tasks can be shown by ID.
+    Show {
+        /// Index or ID of the task.
+        task: TaskRef,
+    },
+    /// Leave the current sub-stack, returning to the stack containing it.
+    Surface,
     /// Swap two tasks
     Swap {
-        task1: TaskIndex,
-        task2: TaskIndex,
+        /// Index or ID of a task.
+        task1: TaskRef,
+        /// Index or ID of another task on the same stack.
+        task2: TaskRef,
     },
     /// Switch to another stack.
     Switchto {
         /// Stack to switch to. Must exist.
         stack: String,
     },
+    /// Show how long tasks spent on top of the current stack, most time first.
+    Time {
+        /// Add up the time per stack instead of per task.
+        #[arg(long, conflicts_with = "by_day")]
+        by_stack: bool,
+        /// Add up the time per day instead of per task, oldest first.
+        #[arg(long)]
+        by_day: bool,
+        /// Only count time on or after this date (YYYY-MM-DD).
+        #[arg(long, value_parser = parse_date)]
+        since: Option<NaiveDate>,
+        /// Only count time on or before this date (YYYY-MM-DD).
+        #[arg(long, value_parser = parse_date)]
+        until: Option<NaiveDate>,
+    },
     /// Trigger a previously-created reminder.
     Triggerreminder {
         reminder_id: String,
     },
+    /// Remove the binding that applies in this directory.
+    Unbind,
+    /// Undo the last command that changed any stack.
+    Undo,
+}
+
+#[derive(Subcommand)]
+enum RemindersCommand {
+    /// Cancel a reminder.
+    Cancel {
+        /// ID of the reminder, or the start of it.
+        reminder: String,
+    },
+    /// Put a reminder off, counting from when it's due.
+    Snooze {
+        /// ID of the reminder, or the start of it.
+        reminder: String,
+        /// How long to put it off, like '10m' or '1h30m'.
+        delay: String,
+    },
+    /// Change when a reminder fires.
+    Reschedule {
+        /// ID of the reminder, or the start of it.
+        reminder: String,
+        /// When to fire it instead. Same format as for remindme.
+        time: String,
+    },
 }
 
+#[derive(Subcommand)]
+enum DbCommand {
+    /// Apply pending schema migrations.
+    Migrate {
+        /// Only report the database's schema version and pending migrations.
+        #[arg(long)]
+        status: bool,
+    },
+    /// Renormalize the order of tasks in every stack, evenly spacing them again.
+    Compact,
+}
 
 
-fn app_main() -> Result<(), Box<dyn StdError>> {
-    let mut os_args: Vec<OsString> = env::args_os().collect();
-    if os_args.len() > 1 {
-        let raw_command = os_args[1].to_str().unwrap();
-        os_args[1] = resolve_command(raw_command)?.into();
 
+fn app_main(format: &mut OutputFormat) -> Result<(), Box<dyn StdError>> {
+    let mut os_args: Vec<OsString> = env::args_os().collect();
+    let mut command_line = String::new();
+    if let Some(i) = subcommand_position(&os_args) {
+        let raw_command = os_args[i].to_str().unwrap();
+        os_args[i] = resolve_command(raw_command).map_err(AppError::from)?.into();
+        // Recorded in the journal to describe what undo and redo act on.
+        command_line = os_args[i..].iter().map(|arg| arg.to_string_lossy()).collect::<Vec<_>>().join(" ");
     }
     let os_args = os_args;
-    let cli = Cli::parse_from(os_args.into_iter());
-    let mut db_path = std::env::temp_dir();
-    db_path.push("yakstack.db");
-    let mut conn = Connection::open(&db_path)
-                              .map_err(|e| format!("unable to open yakstack database: {}", e))?;
-    // DB could be locked by a previous remind command.
-    conn.busy_timeout(Duration::from_secs(1))?;
-    conn.execute("PRAGMA foreign_keys = ON", [])?;
-    if !is_db_initialized(&conn) {
-        init_db(&mut conn)?;
+    let cli = Cli::parse_from(os_args);
+    // Document formats report errors as plain text.
+    *format = cli.format.output_format().unwrap_or_default();
+    let format = *format;
+    let document_format = cli.format.document_format();
+    let supports_format = match cli.command {
+        Command::Export { .. } => document_format.is_some(),
+        Command::Import { .. } => document_format.is_some_and(DocumentFormat::can_read),
+        _ => cli.format.output_format().is_some(),
+    };
+    if !supports_format {
+        let format_name = cli.format.to_possible_value().expect("bug: format has no name").get_name().to_string();
+        let command_name = command_line.split(' ').next().unwrap_or_default().to_string();
+        return Err(AppError::from(CommandError::UnsupportedFormat(format_name, command_name)).into());
     }
+    let document_format = document_format.unwrap_or_default();
+    let use_default_db = cli.db.is_none();
+    let db_path = match cli.db {
+        Some(path) => path,
+        None => paths::default_db_path()?,
+    };
+    paths::ensure_db_dir(&db_path)?;
+    if use_default_db && paths::move_legacy_db(&db_path)? {
+        eprintln!("Moved database from {} to {}", paths::legacy_db_path().display(), db_path.display());
+    }
+    let mut yak = YakStack::open_unmigrated(&db_path)?;
+    if let Command::Db { command: DbCommand::Migrate { status: true } } = cli.command {
+        return Ok(print_migration_status(&yak)?);
+    }
+    let applied_migrations = yak.migrate()?;
+    // Without a working directory, as when it's been deleted, no binding applies.
+    let working_dir = env::current_dir().and_then(fs::canonicalize).ok();
+    let uses_binding = !matches!(cli.command, Command::Bind { .. } | Command::Unbind | Command::Db { .. } | Command::Triggerreminder { .. });
+    #[cfg(unix)]
+    let uses_binding = uses_binding && !matches!(cli.command, Command::Daemon);
+    let binding = match &working_dir {
+        Some(dir) if uses_binding => yak.use_binding(dir)?,
+        _ => None,
+    };
     match cli.command {
-        Command::Add { task }=> {
-            push_task(&conn, task)?;
+        Command::Add { task, tags }=> {
+            yak.record(&command_line, |yak| {
+                let task = yak.push(task)?;
+                yak.tag(TaskRef::Id(task.id), &tags)
+            })?;
         },
-        Command::Backpush { task }=> {
-            pushback_task(&conn, task)?;
+        Command::Backpush { task, tags }=> {
+            yak.record(&command_line, |yak| {
+                let task = yak.push_back(task)?;
+                yak.tag(TaskRef::Id(task.id), &tags)
+            })?;
         },
         Command::Pop { name }=> {
             if let Some(name) = name {
-                return Ok(pop_to(&conn, name)?);
+                yak.record(&command_line, |yak| yak.pop_to(&name))?;
+                return Ok(());
             }
 
-            if let Some(task) = pop_task(&conn)? {
-                println!("{} ✔️", task);
+            let context = yak.context()?;
+            if let Some(task) = yak.record(&command_line, |yak| yak.pop())? {
+                output::print_finished_task(format, &task);
+                if format == OutputFormat::Plain && yak.context()? != context {
+                    print_context(&yak)?;
+                }
             } else {
-                return Err(TaskError::NoTasks.into());
+                return Err(AppError::from(TaskError::NoTasks).into());
             }
         }
+        Command::Move { tasks, stack, top: _, bottom, after } => {
+            let position = match after {
+                Some(after) => MovePosition::After(after),
+                None if bottom => MovePosition::Bottom,
+                None => MovePosition::Top,
+            };
+            yak.record(&command_line, |yak| yak.move_tasks(tasks, &stack, position))?;
+        }
+        Command::Peek => output::print_task(format, &yak.peek()?),
+        Command::Note { task, text, edit: _ } => {
+            // The editor runs before recording, so the database isn't locked while it's open.
+            let text = match text {
+                Some(text) => text,
+                None => edit_in_editor("")?,
+            };
+            yak.record(&command_line, |yak| yak.note(task, text))?;
+        }
+        Command::Edit { task: Some(task), text, .. } => {
+            // As with notes, the editor runs before recording.
+            let text = match text {
+                Some(text) => text,
+                None => edit_in_editor(&yak.show(task)?.task.task)?.trim().to_string(),
+            };
+            yak.record(&command_line, |yak| yak.edit(task, text))?;
+        }
+        Command::Edit { task: None, regex, stack, .. } => {
+            let substitution = regex.expect("bug: clap allowed edit without a task or --regex");
+            let edited = yak.record(&command_line, |yak| yak.substitute(stack.as_deref(), &substitution))?;
+            output::print_found_tasks(format, &edited);
+        }
+        Command::Show { task } => output::print_task_details(format, &yak.show(task)?, Utc::now().timestamp()),
+        Command::Roll { depth } => {
+            yak.record(&command_line, |yak| yak.roll(depth))?;
+        }
+        Command::Rot => {
+            yak.record(&command_line, |yak| yak.roll(2))?;
+        }
+        Command::Bury => {
+            yak.record(&command_line, |yak| yak.bury())?;
+        }
+        Command::Dup => {
+            yak.record(&command_line, |yak| yak.dup(0))?;
+        }
+        Command::Over => {
+            yak.record(&command_line, |yak| yak.dup(1))?;
+        }
         Command::Swap { task1, task2 }=> {
-            swap_tasks(&mut conn, task1, task2)?;
-        }
-        Command::Clear => clear_tasks(&conn)?,
-        Command::Clearall => clear_all_tasks(&conn)?,
-        Command::Ls => {
-            println!("Stack: {}", get_current_stack_name(&conn)?);
-            list_tasks(&conn)?.iter().enumerate().for_each(|(i, task)| println!("{}. {}", i, task));
-        }
-        Command::Newstack { name } => new_stack(&conn, name)?,
-        Command::Switchto { stack } => switch_to_stack(&conn, stack)?,
-        Command::Dropstack { stack } => drop_stack(&mut conn, stack)?,
-        Command::Liststacks => list_stacks(&conn)?.iter().for_each(|stack| println!("{}", stack)),
+            yak.record(&command_line, |yak| yak.swap(task1, task2))?;
+        }
+        Command::Clear => yak.record(&command_line, |yak| yak.clear())?,
+        Command::Clearall => yak.record(&command_line, |yak| yak.clear_all())?,
+        Command::Ls { tree: false, tags, not_tags, all_stacks } => {
+            let filter = TagFilter { tags, not_tags };
+            if all_stacks {
+                output::print_found_tasks(format, &yak.find_tasks(&filter)?);
+            } else {
+                let tasks: Vec<Task> = yak.tasks()?.into_iter().filter(|task| filter.matches(task)).collect();
+                output::print_tasks(format, &yak.context_path()?, binding.as_ref(), &tasks);
+            }
+        }
+        Command::Ls { tree: true, .. } => {
+            output::print_task_tree(format, &yak.current_stack()?.name, binding.as_ref(), &yak.task_tree()?);
+        }
+        Command::Dive => {
+            yak.record(&command_line, |yak| yak.dive())?;
+            if format == OutputFormat::Plain {
+                print_context(&yak)?;
+            }
+        }
+        Command::Surface => {
+            yak.record(&command_line, |yak| yak.surface())?;
+            if format == OutputFormat::Plain {
+                print_context(&yak)?;
+            }
+        }
+        Command::Log { stack, all_stacks, since, until } => {
+            let stack = match stack {
+                _ if all_stacks => None,
+                Some(stack) => Some(stack),
+                None => Some(yak.current_stack()?.name),
+            };
+            let (since, until) = day_bounds(since, until);
+            output::print_log(format, &yak.finished_tasks(stack.as_deref(), since, until)?, all_stacks);
+        }
+        Command::Time { by_stack, by_day, since, until } => {
+            let grouping = match (by_stack, by_day) {
+                (true, _) => TimeGrouping::Stack,
+                (_, true) => TimeGrouping::Day,
+                _ => TimeGrouping::Task,
+            };
+            let (since, until) = day_bounds(since, until);
+            output::print_time_totals(format, &yak.time_totals(grouping, since, until)?);
+        }
+        Command::Pause => output::print_timed_task(format, "Paused", yak.pause()?.as_ref()),
+        Command::Resume => output::print_timed_task(format, "Resumed", yak.resume()?.as_ref()),
+        Command::Newstack { name } => {
+            yak.record(&command_line, |yak| yak.new_stack(&name))?;
+        }
+        Command::Switchto { stack } => {
+            yak.record(&command_line, |yak| yak.switch_to(&stack))?;
+            if let Some(binding) = binding.filter(|binding| binding.stack != stack) {
+                eprintln!("Note: {} is bound to stack '{}', so commands run there still use it.", binding.path.display(), binding.stack);
+            }
+        }
+        Command::Bind { stack, here } => {
+            let working_dir = require_working_dir(working_dir.as_deref())?;
+            let dir = if here { working_dir.to_path_buf() } else { paths::binding_dir(working_dir) };
+            let binding = yak.record(&command_line, |yak| {
+                let binding = yak.bind(stack.as_deref(), &dir)?;
+                yak.use_binding(working_dir)?;
+                Ok(binding)
+            })?;
+            println!("Bound stack '{}' to {}", binding.stack, binding.path.display());
+        }
+        Command::Unbind => {
+            let working_dir = require_working_dir(working_dir.as_deref())?;
+            let binding = yak.record(&command_line, |yak| yak.unbind(working_dir))?;
+            println!("Unbound stack '{}' from {}", binding.stack, binding.path.display());
+        }
+        Command::Dropstack { stack } => yak.record(&command_line, |yak| yak.drop_stack(&stack))?,
+        Command::Renamestack { old_name, new_name } => {
+            yak.record(&command_line, |yak| yak.rename_stack(&old_name, &new_name))?;
+        }
+        Command::Copystack { source, destination } => {
+            yak.record(&command_line, |yak| yak.copy_stack(&source, &destination))?;
+        }
+        Command::Mergestack { source, destination, on_top: _, below } => {
+            let position = if below { MergePosition::Below } else { MergePosition::OnTop };
+            let destination = merge_destination(destination).unwrap_or_else(|e| e.exit());
+            yak.record(&command_line, |yak| yak.merge_stack(&source, &destination, position))?;
+        }
+        Command::Liststacks => output::print_stacks(format, &yak.stacks()?),
         Command::Kill { task }=> {
-            let killed = kill_task(&mut conn, task)?;
-            println!("{} 🗑️", killed);
+            let killed = yak.record(&command_line, |yak| yak.kill(task))?;
+            output::print_finished_task(format, &killed);
+        }
+        Command::Remindme { task, delay }=> {
+            yak.record(&command_line, |yak| yak.remind(task, &delay))?;
+        }
+        Command::Reminders { command: None } => output::print_reminders(format, &yak.reminders()?, Utc::now().timestamp()),
+        Command::Reminders { command: Some(RemindersCommand::Cancel { reminder }) } => {
+            yak.record(&command_line, |yak| yak.cancel_reminder(&reminder))?;
+        }
+        Command::Reminders { command: Some(RemindersCommand::Snooze { reminder, delay }) } => {
+            yak.record(&command_line, |yak| yak.snooze_reminder(&reminder, &delay))?;
+        }
+        Command::Reminders { command: Some(RemindersCommand::Reschedule { reminder, time }) } => {
+            yak.record(&command_line, |yak| yak.reschedule_reminder(&reminder, &time))?;
+        }
+        Command::Triggerreminder { reminder_id }=> yak.trigger_reminder(&reminder_id)?,
+        #[cfg(unix)]
+        Command::Daemon => yak.run_daemon()?,
+        Command::Insertafter { task, after, tags } => {
+            yak.record(&command_line, |yak| {
+                let task = yak.insert_after(after, task)?;
+                yak.tag(TaskRef::Id(task.id), &tags)
+            })?;
+        }
+        Command::Export { stack } => print!("{}", yak.export(stack.as_deref())?.write(document_format)),
+        Command::Import { file, merge: _, replace } => {
+            let document = Document::read(document_format, &read_input(&file)?, &yak.current_stack()?.name)?;
+            let mode = if replace { ImportMode::Replace } else { ImportMode::Merge };
+            yak.record(&command_line, |yak| yak.import(&document, mode))?;
+            println!("Imported {} task(s) on {} stack(s).", document.task_count(), document.stacks.len());
+        }
+        Command::Undo => println!("Undid: {}", yak.undo()?),
+        Command::Redo => println!("Redid: {}", yak.redo()?),
+        Command::Db { command: DbCommand::Compact } => {
+            let changed = yak.record(&command_line, |yak| yak.compact())?;
+            println!("Reordered {} task(s).", changed);
+        }
+        Command::Db { command: DbCommand::Migrate { .. } } => {
+            if applied_migrations.is_empty() {
+                println!("Database is up to date (schema version {}).", migrations::latest_version());
+            }
+            for version in applied_migrations {
+                println!("Applied migration {}: {}", version, migrations::MIGRATIONS[version as usize - 1].description);
+            }
         }
-        Command::Remindme { task, delay }=> remind_me(&mut conn, task, delay)?,
-        Command::Triggerreminder { reminder_id }=> trigger_reminder(db_path, conn, reminder_id)?,
-        Command::Insertafter { task, after } => insert_after(&mut conn, after, task)?,
     }
     Ok(())
 }
 
+/// Print the current stack, and the sub-stack within it if any.
+fn print_context(yak: &YakStack) -> yakstack::AppResult<()> {
+    println!("Stack: {}", yak.context_path()?.join(" > "));
+    Ok(())
+}
+
+/// Print the schema version of `yak` and every known migration, without applying any.
+fn print_migration_status(yak: &YakStack) -> yakstack::AppResult<()> {
+    let current = yak.schema_version()?;
+    println!("Schema version: {} (latest: {})", current, migrations::latest_version());
+    for (i, migration) in migrations::MIGRATIONS.iter().enumerate() {
+        let version = i as u32 + 1;
+        let mark = if version <= current { "applied" } else { "pending" };
+        println!("{:>4}  {:<8} {}", version, mark, migration.description);
+    }
+    if current > migrations::latest_version() {
+        println!("Database was written by a newer version of yakstack.");
+    }
+    Ok(())
+}
+
+/// Get the working directory, which bind and unbind need.
+fn require_working_dir(working_dir: Option<&Path>) -> yakstack::AppResult<&Path> {
+    working_dir.ok_or_else(|| AppError::Environment("unable to find the current directory".into()))
+}
+
+/// Read all of `path`, or standard input if it's `-`.
+fn read_input(path: &Path) -> yakstack::AppResult<String> {
+    let mut input = String::new();
+    let result = if path == Path::new("-") {
+        io::stdin().read_to_string(&mut input).map(|_| ())
+    } else {
+        File::open(path).and_then(|mut file| file.read_to_string(&mut input)).map(|_| ())
+    };
+    result.map_err(|e| AppError::Environment(format!("unable to read {}: {}", path.display(), e)))?;
+    Ok(input)
+}
+
+/// Let the user edit `initial` in $VISUAL or $EDITOR, falling back to vi, and return the result.
+fn edit_in_editor(initial: &str) -> yakstack::AppResult<String> {
+    let editor = ["VISUAL", "EDITOR"].into_iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".into());
+    // Editors can be given with arguments, like 'code --wait'.
+    let mut words = editor.split_whitespace();
+    let program = words.next().expect("bug: editor is blank");
+    // A new file only we can read and write, so nobody can swap in a symlink
+    // to have the text written elsewhere. It's deleted when dropped.
+    let mut file = tempfile::Builder::new().prefix("yakstack-").suffix(".txt").tempfile()
+        .map_err(|e| AppError::Environment(format!("unable to create a temporary file: {}", e)))?;
+    let path = file.path().to_path_buf();
+    file.write_all(initial.as_bytes()).and_then(|_| file.flush())
+        .map_err(|e| AppError::Environment(format!("unable to write {}: {}", path.display(), e)))?;
+    let status = process::Command::new(program).args(words).arg(&path).status();
+    // Editors may replace the file rather than write to it, so read it afresh.
+    let text = fs::read_to_string(&path);
+    drop(file);
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => return Err(AppError::Environment(format!("{} exited with {}", program, status))),
+        Err(e) => return Err(AppError::Environment(format!("unable to run {}: {}", program, e))),
+    }
+    text.map_err(|e| AppError::Environment(format!("unable to read {}: {}", path.display(), e)))
+}
+
+/// Parse a `YYYY-MM-DD` date.
+fn parse_date(date: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| format!("'{}' is not a YYYY-MM-DD date", date))
+}
+
+/// Get the first moment of the day `since` and the first moment after the day
+/// `until`, so that both days are included.
+fn day_bounds(since: Option<NaiveDate>, until: Option<NaiveDate>) -> (Option<Timestamp>, Option<Timestamp>) {
+    let since = since.map(timespec::start_of_day);
+    let until = until.and_then(|date| date.succ_opt()).map(timespec::start_of_day);
+    (since, until)
+}
+
+/// Get the stack `mergestack` merges into from its last arguments, `DESTINATION` or `into DESTINATION`.
+fn merge_destination(args: Vec<String>) -> Result<String, clap::Error> {
+    match <[String; 2]>::try_from(args) {
+        Ok([into, destination]) if into == "into" => Ok(destination),
+        Ok([_, unexpected]) => {
+            let mut cli = Cli::command();
+            cli.build();
+            let mergestack = cli.find_subcommand_mut("mergestack").expect("bug: no mergestack command");
+            Err(mergestack.error(ErrorKind::UnknownArgument, format!("unexpected argument '{}' found", unexpected)))
+        }
+        Err(mut args) => Ok(args.pop().expect("bug: mergestack without a destination")),
+    }
+}
+
+/// Global options that are followed by a value, which must not be mistaken for the subcommand.
+static GLOBAL_OPTIONS_WITH_VALUES: &[&str] = &["--db", "--format"];
+
+/// Find the position of the subcommand in `args`, skipping the program name and global options.
+fn subcommand_position(args: &[OsString]) -> Option<usize> {
+    let mut i = 1;
+    while i < args.len() {
+        let arg = args[i].to_string_lossy();
+        if GLOBAL_OPTIONS_WITH_VALUES.contains(&arg.as_ref()) {
+            i += 2;
+        } else if arg.starts_with('-') {
+            i += 1;
+        } else {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// Prefixes that keep meaning the command they used to, though newer commands share them.
+static ABBREVIATIONS: &[(&str, &str)] = &[
+    ("b", "backpush"),
+    ("d", "dropstack"),
+    ("i", "insertafter"),
+    ("n", "newstack"),
+    ("p", "pop"),
+    ("r", "remindme"),
+    ("re", "remindme"),
+    ("rem", "remindme"),
+    ("remi", "remindme"),
+    ("remin", "remindme"),
+    ("remind", "remindme"),
+    ("t", "triggerreminder"),
+    ("u", "undo"),
+    ("un", "undo"),
+];
+
 /// Resolve a `prefix` into its full command.
 fn resolve_command(prefix: &str) -> Result<&str, CommandError>  {
     if prefix.starts_with('-') {
         return Ok(prefix);
     }
+    if let Some(&(_, command)) = ABBREVIATIONS.iter().find(|(abbreviation, _)| *abbreviation == prefix) {
+        return Ok(command);
+    }
     let mut matcher: &str = "";
     let mut num_matches = 0;
     for &c in COMMANDS {
@@ -211,40 +859,104 @@ fn resolve_command(prefix: &str) -> Result<&str, CommandError>  {
 
 #[cfg(test)]
 mod tests {
-    use crate::resolve_command;
-    use crate::errors::CommandError;
+    use std::ffi::OsString;
+
+    use chrono::NaiveDate;
+    use clap::Parser;
+
+    use crate::{Cli, Command, day_bounds, merge_destination, resolve_command, subcommand_position};
+    use yakstack::errors::CommandError;
+    use yakstack::timespec;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
 
     #[test]
     fn resolve_command_test() {
+        // One-letter prefixes resolve as they did before other commands shared them.
+        for (prefix, command) in [
+            ("a", Some("add")),
+            ("b", Some("backpush")),
+            ("c", None),
+            ("d", Some("dropstack")),
+            ("i", Some("insertafter")),
+            ("k", Some("kill")),
+            ("l", None),
+            ("n", Some("newstack")),
+            ("p", Some("pop")),
+            ("r", Some("remindme")),
+            ("s", None),
+            ("t", Some("triggerreminder")),
+        ] {
+            assert_eq!(resolve_command(prefix).ok(), command, "{prefix}");
+        }
         assert!(matches!(resolve_command("l"), Err(CommandError::AmbiguousPrefix(_))));
         assert!(matches!(resolve_command("xxx"), Err(CommandError::NoMatchingCommand(_))));
         assert!(matches!(resolve_command("b"), Ok("backpush")));
+        assert!(matches!(resolve_command("bi"), Ok("bind")));
+        assert!(matches!(resolve_command("i"), Ok("insertafter")));
+        assert!(matches!(resolve_command("n"), Ok("newstack")));
+        assert!(matches!(resolve_command("no"), Ok("note")));
+        assert!(matches!(resolve_command("p"), Ok("pop")));
+        assert!(matches!(resolve_command("ro"), Err(CommandError::AmbiguousPrefix(_))));
+        assert!(matches!(resolve_command("unb"), Ok("unbind")));
+    }
+
+    #[test]
+    fn baseline_prefixes_resolve_as_before() {
+        // The commands there were before any of the ones added since.
+        let baseline = ["add", "backpush", "clear", "clearall", "dropstack", "insertafter", "kill", "liststacks",
+            "ls", "newstack", "pop", "remindme", "swap", "switchto", "triggerreminder"];
+        for command in baseline {
+            for end in 1..=command.len() {
+                let prefix = &command[..end];
+                let matches: Vec<&str> = baseline.into_iter().filter(|other| other.starts_with(prefix)).collect();
+                let resolved = match matches[..] {
+                    _ if matches.contains(&prefix) => prefix,
+                    [only] => only,
+                    _ => continue,
+                };
+                assert_eq!(resolve_command(prefix).ok(), Some(resolved), "{prefix}");
+            }
+        }
     }
 
     #[test]
     fn resolve_command_command_prefixes_other_command_works() {
         assert!(matches!(resolve_command("clear"), Ok("clear")));
     }
-}
 
-/// Check whether `db` is initialized.
-fn is_db_initialized(db: &Connection) -> bool {
-    get_current_stack_id(db).is_ok()
-}
+    #[test]
+    fn subcommand_position_skips_global_options() {
+        assert_eq!(subcommand_position(&args(&["yakstack", "ls"])), Some(1));
+        assert_eq!(subcommand_position(&args(&["yakstack", "--db", "ls", "pop"])), Some(3));
+        assert_eq!(subcommand_position(&args(&["yakstack", "--db=x.db", "ls"])), Some(2));
+        assert_eq!(subcommand_position(&args(&["yakstack", "--help"])), None);
+    }
 
-/// Initialize `db` with application tables.
-fn init_db(db: &mut Connection) -> AppResult<()> {
-    let xact = db.transaction()?;
-    xact.execute("PRAGMA foreign_keys = ON", [])?;
-    xact.execute("CREATE TABLE IF NOT EXISTS stacks(id INTEGER PRIMARY KEY, name TEXT NOT NULL, UNIQUE(name)) STRICT", [])?;
-    xact.execute("CREATE TABLE IF NOT EXISTS app_state(id INTEGER PRIMARY KEY, stack_id INTEGER NOT NULL, FOREIGN KEY(stack_id) REFERENCES stacks(id), CHECK (id = 1)) STRICT", [])?;
-    xact.execute("CREATE TABLE IF NOT EXISTS tasks(task TEXT NOT NULL, task_order REAL NOT NULL, id INTEGER PRIMARY KEY, stack_id INTEGER NOT NULL, FOREIGN KEY(stack_id) REFERENCES stacks(id), CHECK (task_order = task_order)) STRICT", [])?;
-    // reminders PK should be a UUID
-    xact.execute("CREATE TABLE IF NOT EXISTS reminders(id TEXT PRIMARY KEY, delay INTEGER NOT NULL, task_id INTEGER NOT NULL REFERENCES tasks(id) ON DELETE CASCADE, CHECK (delay > 0)) STRICT", [])?;
-    xact.execute("CREATE INDEX IF NOT EXISTS tasks_ix ON tasks(stack_id, task_order, task)", [])?;
-    xact.execute("INSERT INTO stacks(id, name) VALUES (?, 'default')", params![DEFAULT_STACK_ID])?;
-    xact.execute("INSERT INTO app_state(stack_id) VALUES (?)", params![DEFAULT_STACK_ID])?;
-    xact.commit()?;
-    Ok(())
-}
+    #[test]
+    fn mergestack_takes_an_optional_into() {
+        for line in [&["yakstack", "mergestack", "a", "b"][..], &["yakstack", "mergestack", "a", "into", "b"], &["yakstack", "mergestack", "into", "into", "b"]] {
+            let Command::Mergestack { destination, .. } = Cli::try_parse_from(line).unwrap().command else {
+                panic!("not parsed as mergestack: {:?}", line);
+            };
+            assert_eq!(merge_destination(destination).unwrap(), "b");
+        }
+        let Command::Mergestack { destination, .. } = Cli::try_parse_from(["yakstack", "mergestack", "a", "to", "b"]).unwrap().command else {
+            panic!("not parsed as mergestack");
+        };
+        assert!(merge_destination(destination).is_err());
+        assert!(Cli::try_parse_from(["yakstack", "mergestack", "a", "into", "b", "c"]).is_err());
+    }
 
+    #[test]
+    fn until_includes_its_whole_day() {
+        let day = |d| NaiveDate::from_ymd_opt(2026, 3, d).unwrap();
+        assert_eq!(day_bounds(None, None), (None, None));
+        let (since, until) = day_bounds(Some(day(1)), Some(day(1)));
+        assert_eq!((since, until), (Some(timespec::start_of_day(day(1))), Some(timespec::start_of_day(day(2)))));
+        // The last second of the day is still before `until`, which is exclusive.
+        assert!(timespec::start_of_day(day(1)) + 23 * 60 * 60 + 59 * 60 + 59 < until.unwrap());
+    }
+}
@@ -0,0 +1,196 @@
+use crate::errors::*;
+
+use rusqlite::Connection;
+
+/// A single schema migration.
+///
+/// Migrations are applied in order, each inside its own transaction. The
+/// version of a migration is its 1-based position in [`MIGRATIONS`], and the
+/// version of a database is stored in `PRAGMA user_version`.
+pub struct Migration {
+    /// Short human-readable summary, shown by `db migrate --status`.
+    pub description: &'static str,
+    sql: &'static str,
+}
+
+/// All migrations, oldest first. Never edit or reorder an existing entry;
+/// append a new one instead.
+pub static MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "initial schema",
+        // Databases created before migrations existed already have these tables
+        // but a user_version of 0, so everything here must be idempotent.
+        // The default stack's ID is DEFAULT_STACK_ID.
+        sql: "
+            CREATE TABLE IF NOT EXISTS stacks(id INTEGER PRIMARY KEY, name TEXT NOT NULL, UNIQUE(name)) STRICT;
+            CREATE TABLE IF NOT EXISTS app_state(id INTEGER PRIMARY KEY, stack_id INTEGER NOT NULL, FOREIGN KEY(stack_id) REFERENCES stacks(id), CHECK (id = 1)) STRICT;
+            CREATE TABLE IF NOT EXISTS tasks(task TEXT NOT NULL, task_order REAL NOT NULL, id INTEGER PRIMARY KEY, stack_id INTEGER NOT NULL, FOREIGN KEY(stack_id) REFERENCES stacks(id), CHECK (task_order = task_order)) STRICT;
+            CREATE TABLE IF NOT EXISTS reminders(id TEXT PRIMARY KEY, delay INTEGER NOT NULL, task_id INTEGER NOT NULL REFERENCES tasks(id) ON DELETE CASCADE, CHECK (delay > 0)) STRICT;
+            CREATE INDEX IF NOT EXISTS tasks_ix ON tasks(stack_id, task_order, task);
+            INSERT OR IGNORE INTO stacks(id, name) VALUES (1, 'default');
+            INSERT OR IGNORE INTO app_state(id, stack_id) VALUES (1, 1);
+        ",
+    },
+    Migration {
+        description: "keep finished tasks as history",
+        sql: "
+            ALTER TABLE tasks ADD COLUMN status TEXT NOT NULL DEFAULT 'open' CHECK (status IN ('open', 'done', 'abandoned'));
+            ALTER TABLE tasks ADD COLUMN created_at INTEGER;
+            ALTER TABLE tasks ADD COLUMN finished_at INTEGER;
+            CREATE INDEX tasks_finished_ix ON tasks(status, finished_at);
+        ",
+    },
+    Migration {
+        description: "undo/redo journal",
+        sql: "
+            CREATE TABLE journal(id INTEGER PRIMARY KEY, command TEXT NOT NULL, created_at INTEGER NOT NULL, undone INTEGER NOT NULL DEFAULT 0, CHECK (undone IN (0, 1))) STRICT;
+            CREATE TABLE journal_changes(id INTEGER PRIMARY KEY, journal_id INTEGER NOT NULL REFERENCES journal(id) ON DELETE CASCADE, undo_sql TEXT NOT NULL, redo_sql TEXT NOT NULL) STRICT;
+            CREATE INDEX journal_changes_ix ON journal_changes(journal_id);
+        ",
+    },
+    Migration {
+        description: "nested sub-stacks",
+        sql: "
+            ALTER TABLE tasks ADD COLUMN parent_id INTEGER REFERENCES tasks(id) ON DELETE CASCADE;
+            ALTER TABLE app_state ADD COLUMN parent_id INTEGER REFERENCES tasks(id) ON DELETE SET NULL;
+            CREATE INDEX tasks_parent_ix ON tasks(parent_id);
+        ",
+    },
+    Migration {
+        description: "absolute reminder due times",
+        // When existing reminders were created isn't recorded, so count their
+        // delay from now; their triggerreminder processes still fire them on time.
+        sql: "
+            ALTER TABLE reminders ADD COLUMN due_at INTEGER NOT NULL DEFAULT 0;
+            UPDATE reminders SET due_at = unixepoch() + delay;
+            CREATE INDEX reminders_due_ix ON reminders(due_at);
+        ",
+    },
+    Migration {
+        description: "cancellable reminders",
+        sql: "
+            ALTER TABLE reminders ADD COLUMN status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'cancelled'));
+        ",
+    },
+    Migration {
+        description: "stacks bound to directories",
+        sql: "
+            CREATE TABLE bindings(id INTEGER PRIMARY KEY, path TEXT NOT NULL UNIQUE, stack_id INTEGER NOT NULL REFERENCES stacks(id) ON DELETE CASCADE) STRICT;
+        ",
+    },
+    Migration {
+        description: "task tags",
+        sql: "
+            CREATE TABLE tags(id INTEGER PRIMARY KEY, task_id INTEGER NOT NULL REFERENCES tasks(id) ON DELETE CASCADE, tag TEXT NOT NULL, UNIQUE(task_id, tag)) STRICT;
+            CREATE INDEX tags_tag_ix ON tags(tag);
+        ",
+    },
+    Migration {
+        description: "task notes",
+        sql: "
+            CREATE TABLE notes(id INTEGER PRIMARY KEY, task_id INTEGER NOT NULL REFERENCES tasks(id) ON DELETE CASCADE, note TEXT NOT NULL, created_at INTEGER NOT NULL) STRICT;
+            CREATE INDEX notes_task_ix ON notes(task_id);
+        ",
+    },
+    Migration {
+        description: "time tracking",
+        sql: "
+            CREATE TABLE time_entries(id INTEGER PRIMARY KEY, task_id INTEGER NOT NULL REFERENCES tasks(id) ON DELETE CASCADE, started_at INTEGER NOT NULL, stopped_at INTEGER) STRICT;
+            CREATE INDEX time_entries_started_ix ON time_entries(started_at);
+            CREATE TABLE timer(id INTEGER PRIMARY KEY, paused_at INTEGER, CHECK (id = 1)) STRICT;
+            INSERT INTO timer(id, paused_at) VALUES (1, NULL);
+        ",
+    },
+    Migration {
+        description: "keep time spent on deleted tasks",
+        sql: "
+            CREATE TABLE time_entries_new(id INTEGER PRIMARY KEY, task_id INTEGER REFERENCES tasks(id) ON DELETE SET NULL, started_at INTEGER NOT NULL, stopped_at INTEGER) STRICT;
+            INSERT INTO time_entries_new(id, task_id, started_at, stopped_at) SELECT id, task_id, started_at, stopped_at FROM time_entries;
+            DROP TABLE time_entries;
+            ALTER TABLE time_entries_new RENAME TO time_entries;
+            CREATE INDEX time_entries_started_ix ON time_entries(started_at);
+        ",
+    },
+];
+
+/// The schema version this build of yakstack expects.
+pub fn latest_version() -> u32 {
+    MIGRATIONS.len() as u32
+}
+
+/// Get the schema version of `db`.
+pub fn schema_version(db: &Connection) -> AppResult<u32> {
+    let version: u32 = db.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    Ok(version)
+}
+
+/// Bring `db` up to the latest schema version.
+///
+/// Returns the versions that were applied, which is empty if the database was
+/// already up to date. Returns an error if the database was written by a newer
+/// version of yakstack.
+pub fn migrate(db: &mut Connection) -> AppResult<Vec<u32>> {
+    let current = schema_version(db)?;
+    let latest = latest_version();
+    if current > latest {
+        return Err(MigrationError::SchemaTooNew { found: current, supported: latest }.into());
+    }
+
+    let mut applied = Vec::new();
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        let version = i as u32 + 1;
+        let xact = db.transaction()?;
+        xact.execute_batch(migration.sql)
+            .map_err(|e| MigrationError::Failed { version, reason: e.to_string() })?;
+        xact.pragma_update(None, "user_version", version)?;
+        xact.commit()?;
+        applied.push(version);
+    }
+    Ok(applied)
+}
+
+/// Open a fully migrated in-memory database.
+#[cfg(test)]
+pub fn test_db() -> crate::store::SqliteStore {
+    let mut store = crate::store::SqliteStore::open_in_memory().unwrap();
+    migrate(store.connection_mut()).unwrap();
+    store
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrate_fresh_database() {
+        let mut db = Connection::open_in_memory().unwrap();
+        let applied = migrate(&mut db).unwrap();
+        assert_eq!(applied.len(), MIGRATIONS.len());
+        assert_eq!(schema_version(&db).unwrap(), latest_version());
+        assert!(migrate(&mut db).unwrap().is_empty());
+    }
+
+    #[test]
+    fn migrate_pre_migration_database() {
+        let mut db = Connection::open_in_memory().unwrap();
+        db.execute_batch("
+            CREATE TABLE stacks(id INTEGER PRIMARY KEY, name TEXT NOT NULL, UNIQUE(name)) STRICT;
+            CREATE TABLE app_state(id INTEGER PRIMARY KEY, stack_id INTEGER NOT NULL, FOREIGN KEY(stack_id) REFERENCES stacks(id), CHECK (id = 1)) STRICT;
+            CREATE TABLE tasks(task TEXT NOT NULL, task_order REAL NOT NULL, id INTEGER PRIMARY KEY, stack_id INTEGER NOT NULL, FOREIGN KEY(stack_id) REFERENCES stacks(id), CHECK (task_order = task_order)) STRICT;
+            INSERT INTO stacks(id, name) VALUES (1, 'default');
+            INSERT INTO app_state(stack_id) VALUES (1);
+            INSERT INTO tasks(task, task_order, stack_id) VALUES ('shave yak', 1, 1);
+        ").unwrap();
+        migrate(&mut db).unwrap();
+        let task: String = db.query_row("SELECT task FROM tasks", [], |row| row.get(0)).unwrap();
+        assert_eq!(task, "shave yak");
+        assert_eq!(schema_version(&db).unwrap(), latest_version());
+    }
+
+    #[test]
+    fn migrate_rejects_newer_schema() {
+        let mut db = Connection::open_in_memory().unwrap();
+        db.pragma_update(None, "user_version", latest_version() + 1).unwrap();
+        assert!(matches!(migrate(&mut db), Err(AppError::Migration(MigrationError::SchemaTooNew { .. }))));
+    }
+}
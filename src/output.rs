@@ -0,0 +1,409 @@
+//! Output formats for commands that report tasks and stacks.
+//!
+//! Besides the default human-readable output, there are two formats meant for
+//! scripts, whose structure is kept stable:
+//!
+//! * `json`: one JSON document per command. Tasks are objects with the fields
+//!   of [`Task`], stacks objects with the fields of [`Stack`], and timestamps
+//!   are seconds since the Unix epoch. `ls` prints
+//!   `{"stack": NAME, "substack": [PARENT...], "tasks": [TASK...]}`, plus
+//!   `"binding": {"path": DIR, "stack": NAME}` if a binding chose the stack,
+//!   `ls --tree` the same with each task also having `depth` and
+//!   `is_current`, `ls --all-stacks`, `liststacks`, `log`, `reminders` and `edit --regex` print arrays, `pop`
+//!   and `kill` print the task they removed, and `peek` the top task. Reminders are objects with the fields of [`Reminder`].
+//!   `show` prints an object with the fields of [`TaskDetails`], and `time` an array
+//!   of objects with the fields of [`TimeTotal`], with durations in seconds.
+//!   Errors are printed to stderr as `{"error": {"code": CODE, "message": MESSAGE}}`.
+//! * `tsv`: a header line naming the columns, then one line per task or stack,
+//!   or per note for `show`.
+//!   Lists of tags are comma-separated, with counts after a `:` for stacks.
+//!   Missing values are empty, and tabs, newlines and backslashes are escaped as
+//!   `\t`, `\n` and `\\`.
+
+use yakstack::types::*;
+use yakstack::errors::AppError;
+
+use std::error::Error as StdError;
+
+use chrono::{Local, TimeZone};
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::json;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable text.
+    #[default]
+    Plain,
+    /// JSON documents.
+    Json,
+    /// Tab-separated values with a header line.
+    Tsv,
+}
+
+/// JSON output of `ls`.
+#[derive(Serialize)]
+struct TaskList<'a, T> {
+    stack: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    substack: Option<&'a [String]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    binding: Option<&'a Binding>,
+    tasks: &'a [T],
+}
+
+const TASK_TSV_HEADER: &str = "index\tid\tshort_id\tstatus\tstack\tcreated_at\tfinished_at\treminders\ttask\ttags";
+
+/// Print tasks of the current context, whose enclosing stack and tasks are `path`.
+///
+/// `binding` is the binding that made the stack current, if any.
+pub fn print_tasks(format: OutputFormat, path: &[String], binding: Option<&Binding>, tasks: &[Task]) {
+    match format {
+        OutputFormat::Plain => {
+            println!("Stack: {}{}", path.join(" > "), binding_note(binding));
+            tasks.iter().for_each(|task| println!("{}. {} {}", task.index.unwrap_or_default(), task.short_id, task_text(task)));
+        }
+        OutputFormat::Json => print_json(&TaskList { stack: &path[0], substack: Some(&path[1..]), binding, tasks }),
+        OutputFormat::Tsv => {
+            println!("{}", TASK_TSV_HEADER);
+            tasks.iter().for_each(|task| println!("{}", task_tsv(task)));
+        }
+    }
+}
+
+/// Print every task on `stack`, sub-stacks included.
+pub fn print_task_tree(format: OutputFormat, stack: &str, binding: Option<&Binding>, tree: &[TreeTask]) {
+    match format {
+        OutputFormat::Plain => {
+            println!("Stack: {}{}", stack, binding_note(binding));
+            for task in tree {
+                let marker = if task.is_current { "  ◀" } else { "" };
+                println!("{}{}. {} {}{}", "   ".repeat(task.depth), task.task.index.unwrap_or_default(), task.task.short_id, task_text(&task.task), marker);
+            }
+        }
+        OutputFormat::Json => print_json(&TaskList { stack, substack: None, binding, tasks: tree }),
+        OutputFormat::Tsv => {
+            println!("depth\tis_current\t{}", TASK_TSV_HEADER);
+            tree.iter().for_each(|task| println!("{}\t{}\t{}", task.depth, task.is_current, task_tsv(&task.task)));
+        }
+    }
+}
+
+/// Print open tasks from any stack.
+pub fn print_found_tasks(format: OutputFormat, tasks: &[Task]) {
+    match format {
+        OutputFormat::Plain => tasks.iter().for_each(|task| println!("{} {} [{}]", task.short_id, task_text(task), task.stack)),
+        OutputFormat::Json => print_json(&tasks),
+        OutputFormat::Tsv => {
+            println!("{}", TASK_TSV_HEADER);
+            tasks.iter().for_each(|task| println!("{}", task_tsv(task)));
+        }
+    }
+}
+
+/// Print all stacks.
+pub fn print_stacks(format: OutputFormat, stacks: &[Stack]) {
+    let tag_counts = |stack: &Stack, separator: &str| {
+        stack.tags.iter().map(|(tag, count)| format!("{}{}{}", tag, separator, count)).collect::<Vec<_>>()
+    };
+    match format {
+        OutputFormat::Plain => {
+            for stack in stacks {
+                if stack.tags.is_empty() {
+                    println!("{}", stack.name);
+                } else {
+                    println!("{} (#{})", stack.name, tag_counts(stack, ": ").join(", #"));
+                }
+            }
+        }
+        OutputFormat::Json => print_json(&stacks),
+        OutputFormat::Tsv => {
+            println!("id\tname\ttasks\tcurrent\ttags");
+            for stack in stacks {
+                println!("{}\t{}\t{}\t{}\t{}", stack.id, tsv_escape(&stack.name), stack.tasks, stack.current, tsv_escape(&tag_counts(stack, ":").join(",")));
+            }
+        }
+    }
+}
+
+/// Print finished tasks. Plain output only shows stack names if `show_stack` is set.
+pub fn print_log(format: OutputFormat, tasks: &[Task], show_stack: bool) {
+    match format {
+        OutputFormat::Plain => {
+            for task in tasks {
+                let finished_at = local_time(task.finished_at.unwrap_or_default());
+                if show_stack {
+                    println!("{} {} {} [{}]", finished_at, status_mark(task.status), task.task, task.stack);
+                } else {
+                    println!("{} {} {}", finished_at, status_mark(task.status), task.task);
+                }
+            }
+        }
+        OutputFormat::Json => print_json(&tasks),
+        OutputFormat::Tsv => {
+            println!("{}", TASK_TSV_HEADER);
+            tasks.iter().for_each(|task| println!("{}", task_tsv(task)));
+        }
+    }
+}
+
+/// Print pending reminders, with how long after `now` each is due.
+pub fn print_reminders(format: OutputFormat, reminders: &[Reminder], now: Timestamp) {
+    match format {
+        OutputFormat::Plain => {
+            for reminder in reminders {
+                // Short IDs are enough to refer to reminders, as long as they're unique.
+                println!("{}  {:>10}  {} [{}]", short_reminder_id(&reminder.id), time_remaining(reminder.due_at - now), reminder.task, reminder.stack);
+            }
+        }
+        OutputFormat::Json => print_json(&reminders),
+        OutputFormat::Tsv => {
+            println!("id\ttask_id\tstack\tdue_at\ttask");
+            for reminder in reminders {
+                println!("{}\t{}\t{}\t{}\t{}", reminder.id, reminder.task_id, tsv_escape(&reminder.stack), reminder.due_at, tsv_escape(&reminder.task));
+            }
+        }
+    }
+}
+
+/// Print a single open task.
+pub fn print_task(format: OutputFormat, task: &Task) {
+    match format {
+        OutputFormat::Plain => println!("{} {}", task.short_id, task.task),
+        OutputFormat::Json => print_json(task),
+        OutputFormat::Tsv => {
+            println!("{}", TASK_TSV_HEADER);
+            println!("{}", task_tsv(task));
+        }
+    }
+}
+
+/// Print a task with its notes and reminders, with how long after `now` each reminder is due.
+pub fn print_task_details(format: OutputFormat, details: &TaskDetails, now: Timestamp) {
+    let task = &details.task;
+    match format {
+        OutputFormat::Plain => {
+            println!("{} {}", task.short_id, task_text(task));
+            println!("Stack: {}", task.stack);
+            if task.status != TaskStatus::Open {
+                println!("Status: {}", task.status.as_str());
+            }
+            if let Some(created_at) = task.created_at {
+                println!("Created: {}", local_time(created_at));
+            }
+            if let Some(finished_at) = task.finished_at {
+                println!("Finished: {}", local_time(finished_at));
+            }
+            for reminder in &details.reminders {
+                println!("Reminder: {} {}", short_reminder_id(&reminder.id), time_remaining(reminder.due_at - now));
+            }
+            for note in &details.notes {
+                println!();
+                println!("{}", local_time(note.created_at));
+                note.note.lines().for_each(|line| println!("  {}", line));
+            }
+        }
+        OutputFormat::Json => print_json(details),
+        OutputFormat::Tsv => {
+            println!("task_id\tcreated_at\tnote");
+            for note in &details.notes {
+                println!("{}\t{}\t{}", task.id, note.created_at, tsv_escape(&note.note));
+            }
+        }
+    }
+}
+
+/// Print the time spent on tasks, stacks or days, followed by the total in plain output.
+pub fn print_time_totals(format: OutputFormat, totals: &[TimeTotal]) {
+    match format {
+        OutputFormat::Plain => {
+            for total in totals {
+                let task_id = total.task_id.map(|id| format!("{} ", TaskRef::Id(id))).unwrap_or_default();
+                println!("{:>8}  {}{}", duration(total.seconds), task_id, total.name);
+            }
+            println!("{:>8}  total", duration(totals.iter().map(|total| total.seconds).sum()));
+        }
+        OutputFormat::Json => print_json(&totals),
+        OutputFormat::Tsv => {
+            println!("name\ttask_id\tseconds");
+            for total in totals {
+                println!("{}\t{}\t{}", tsv_escape(&total.name), total.task_id.map(|id| id.to_string()).unwrap_or_default(), total.seconds);
+            }
+        }
+    }
+}
+
+/// Print the task whose timer was just paused or resumed, as `action`, or
+/// `null` in JSON if no task is being worked on.
+pub fn print_timed_task(format: OutputFormat, action: &str, task: Option<&Task>) {
+    match format {
+        OutputFormat::Plain => match task {
+            Some(task) => println!("{}: {} {}", action, task.short_id, task.task),
+            None => println!("{}.", action),
+        },
+        OutputFormat::Json => print_json(&task),
+        OutputFormat::Tsv => {
+            println!("{}", TASK_TSV_HEADER);
+            task.into_iter().for_each(|task| println!("{}", task_tsv(task)));
+        }
+    }
+}
+
+/// Print a task that was just popped or killed.
+pub fn print_finished_task(format: OutputFormat, task: &Task) {
+    match format {
+        OutputFormat::Plain => println!("{} {}", task.task, status_mark(task.status)),
+        OutputFormat::Json => print_json(task),
+        OutputFormat::Tsv => {
+            println!("{}", TASK_TSV_HEADER);
+            println!("{}", task_tsv(task));
+        }
+    }
+}
+
+/// Print an error to stderr.
+pub fn print_error(format: OutputFormat, error: &(dyn StdError + 'static)) {
+    match format {
+        OutputFormat::Json => {
+            let code = error.downcast_ref::<AppError>().map_or("error", AppError::code);
+            eprintln!("{}", json!({"error": {"code": code, "message": error.to_string()}}));
+        }
+        OutputFormat::Plain | OutputFormat::Tsv => eprintln!("Error: {}", error),
+    }
+}
+
+/// Shorten a reminder's ID for plain output. IDs are UUIDs, but needn't be.
+fn short_reminder_id(id: &str) -> &str {
+    id.get(..8).unwrap_or(id)
+}
+
+/// Describe `binding` for plain output, after the stack's name.
+fn binding_note(binding: Option<&Binding>) -> String {
+    binding.map_or_else(String::new, |binding| format!(" (bound to {})", binding.path.display()))
+}
+
+/// Get a task's text, followed by any tags not written in it.
+fn task_text(task: &Task) -> String {
+    let inline = inline_tags(&task.task);
+    let mut text = task.task.clone();
+    for tag in task.tags.iter().filter(|tag| !inline.contains(tag)) {
+        text.push_str(&format!(" #{}", tag));
+    }
+    text
+}
+
+/// Write `timestamp` as a local date and time, to the minute.
+fn local_time(timestamp: Timestamp) -> String {
+    Local.timestamp_opt(timestamp, 0).unwrap().format("%Y-%m-%d %H:%M").to_string()
+}
+
+fn status_mark(status: TaskStatus) -> &'static str {
+    match status {
+        TaskStatus::Abandoned => "🗑️",
+        TaskStatus::Open | TaskStatus::Done => "✔️",
+    }
+}
+
+/// Describe a number of seconds from now, e.g. `in 1h 5m`, to the minute unless it's under a minute.
+fn time_remaining(seconds: i64) -> String {
+    if seconds <= 0 {
+        return "due".into();
+    }
+    let (days, hours, minutes) = (seconds / 86400, seconds / 3600 % 24, seconds / 60 % 60);
+    match (days, hours, minutes) {
+        (0, 0, 0) => format!("in {}s", seconds),
+        (0, 0, _) => format!("in {}m", minutes),
+        (0, _, _) => format!("in {}h {}m", hours, minutes),
+        _ => format!("in {}d {}h", days, hours),
+    }
+}
+
+/// Describe a number of seconds, e.g. `1h 05m`, to the minute unless it's under a minute.
+fn duration(seconds: i64) -> String {
+    let (hours, minutes) = (seconds / 3600, seconds / 60 % 60);
+    match (hours, minutes) {
+        (0, 0) => format!("{}s", seconds),
+        (0, _) => format!("{}m", minutes),
+        _ => format!("{}h {:02}m", hours, minutes),
+    }
+}
+
+fn print_json(value: &impl Serialize) {
+    println!("{}", serde_json::to_string(value).expect("bug: unable to serialize output"));
+}
+
+fn task_tsv(task: &Task) -> String {
+    let optional = |value: Option<i64>| value.map(|v| v.to_string()).unwrap_or_default();
+    format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+        task.index.map(|i| i.to_string()).unwrap_or_default(),
+        task.id,
+        task.short_id,
+        task.status.as_str(),
+        tsv_escape(&task.stack),
+        optional(task.created_at),
+        optional(task.finished_at),
+        task.reminders,
+        tsv_escape(&task.task),
+        tsv_escape(&task.tags.join(",")))
+}
+
+fn tsv_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(text: &str, tags: &[&str]) -> Task {
+        Task {
+            index: Some(2),
+            id: 0xa3f,
+            short_id: "#a3f".into(),
+            task: text.into(),
+            stack: "work\tstuff".into(),
+            status: TaskStatus::Open,
+            created_at: Some(1700000000),
+            finished_at: None,
+            reminders: 1,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn tsv_lines_match_the_header() {
+        assert_eq!(tsv_escape("a\tb\nc\\d"), "a\\tb\\nc\\\\d");
+        let line = task_tsv(&task("fix #ci\nnow", &["ci", "urgent"]));
+        assert_eq!(line.split('\t').count(), TASK_TSV_HEADER.split('\t').count());
+        assert_eq!(line, "2\t2623\t#a3f\topen\twork\\tstuff\t1700000000\t\t1\tfix #ci\\nnow\tci,urgent");
+    }
+
+    #[test]
+    fn plain_text_shows_tags_not_in_the_text() {
+        assert_eq!(task_text(&task("fix #ci", &["ci", "urgent"])), "fix #ci #urgent");
+        assert_eq!(time_remaining(-5), "due");
+        assert_eq!(time_remaining(45), "in 45s");
+        assert_eq!(time_remaining(3900), "in 1h 5m");
+        assert_eq!(time_remaining(90000), "in 1d 1h");
+        assert_eq!(duration(59), "59s");
+        assert_eq!(duration(3900), "1h 05m");
+        assert_eq!(short_reminder_id("5666fefd-e420-40a2-a2dd-47db03cc3b0a"), "5666fefd");
+        assert_eq!(short_reminder_id("r"), "r");
+        assert_eq!(short_reminder_id("rémindér"), "rémindér");
+    }
+
+    #[test]
+    fn json_has_the_documented_shape() {
+        let tasks = [task("a", &[])];
+        let path = ["work".to_string(), "sub".to_string()];
+        let list = serde_json::to_value(TaskList { stack: &path[0], substack: Some(&path[1..]), binding: None, tasks: &tasks }).unwrap();
+        assert_eq!(list["stack"], "work");
+        assert_eq!(list["substack"], json!(["sub"]));
+        assert!(list.get("binding").is_none());
+        let fields: Vec<&str> = list["tasks"][0].as_object().unwrap().keys().map(String::as_str).collect();
+        assert_eq!(fields, vec!["created_at", "finished_at", "id", "index", "reminders", "short_id", "stack", "status", "tags", "task"]);
+
+        let total = serde_json::to_value(TimeTotal { name: "a".into(), task_id: None, seconds: 5 }).unwrap();
+        assert_eq!(total, json!({"name": "a", "task_id": null, "seconds": 5}));
+    }
+}
@@ -0,0 +1,80 @@
+use crate::errors::*;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const DB_FILE_NAME: &str = "yakstack.db";
+
+/// Get the default database location, `$XDG_DATA_HOME/yakstack/yakstack.db`.
+///
+/// Falls back to `~/.local/share` if `XDG_DATA_HOME` is unset or not absolute,
+/// as the XDG base directory spec requires.
+pub fn default_db_path() -> AppResult<PathBuf> {
+    let data_home = match env::var_os("XDG_DATA_HOME").map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => dir,
+        _ => {
+            let home = env::var_os("HOME")
+                .ok_or_else(|| AppError::Environment("unable to find database location: neither XDG_DATA_HOME nor HOME is set".into()))?;
+            [home.as_os_str(), ".local".as_ref(), "share".as_ref()].iter().collect()
+        }
+    };
+    Ok(data_home.join("yakstack").join(DB_FILE_NAME))
+}
+
+/// Get the location older versions of yakstack kept their database in.
+pub fn legacy_db_path() -> PathBuf {
+    env::temp_dir().join(DB_FILE_NAME)
+}
+
+/// Make sure the directory holding `db_path` exists.
+pub fn ensure_db_dir(db_path: &Path) -> AppResult<()> {
+    if let Some(dir) = db_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)
+            .map_err(|e| AppError::Environment(format!("unable to create database directory {}: {}", dir.display(), e)))?;
+    }
+    Ok(())
+}
+
+/// Move a database left in the temp dir by an older yakstack to `db_path`.
+///
+/// Does nothing if `db_path` already exists or there is no old database.
+/// Returns whether a database was moved.
+pub fn move_legacy_db(db_path: &Path) -> AppResult<bool> {
+    let legacy_path = legacy_db_path();
+    if db_path.exists() || !legacy_path.is_file() {
+        return Ok(false);
+    }
+
+    // A leftover rollback journal belongs with its database.
+    let mut legacy_journal = legacy_path.clone().into_os_string();
+    legacy_journal.push("-journal");
+    let mut journal = db_path.to_path_buf().into_os_string();
+    journal.push("-journal");
+    move_file(&legacy_path, db_path)?;
+    if Path::new(&legacy_journal).is_file() {
+        move_file(Path::new(&legacy_journal), Path::new(&journal))?;
+    }
+    Ok(true)
+}
+
+/// Get the directory to bind stacks to when working in `dir`: the root of the
+/// git repository containing it, if any, or else `dir` itself.
+pub fn binding_dir(dir: &Path) -> PathBuf {
+    // Worktrees and submodules have a .git file rather than a directory.
+    dir.ancestors()
+        .find(|ancestor| ancestor.join(".git").exists())
+        .unwrap_or(dir)
+        .to_path_buf()
+}
+
+/// Move `from` to `to`, copying if they are on different filesystems.
+fn move_file(from: &Path, to: &Path) -> AppResult<()> {
+    let map_err = |e: std::io::Error| AppError::Environment(format!("unable to move {} to {}: {}", from.display(), to.display(), e));
+    if fs::rename(from, to).is_err() {
+        // The temp dir is frequently a tmpfs, so rename() fails with EXDEV.
+        fs::copy(from, to).map_err(map_err)?;
+        fs::remove_file(from).map_err(map_err)?;
+    }
+    Ok(())
+}
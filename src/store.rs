@@ -0,0 +1,176 @@
+//! Storage backends.
+//!
+//! A [`Store`] keeps stacks, tasks, reminders and the current context, and
+//! knows nothing about how they're used: the rules for ordering tasks,
+//! sub-stacks and so on are implemented once on top of the trait. Stores only
+//! need to do what each method says.
+//!
+//! [`SqliteStore`] is the real thing. [`MemoryStore`] keeps everything in
+//! memory, which makes it handy for testing.
+
+use crate::errors::*;
+use crate::types::*;
+
+use std::path::{Path, PathBuf};
+
+mod memory;
+mod sqlite;
+
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+/// A stack, as stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackRecord {
+    pub id: StackId,
+    pub name: String,
+}
+
+/// A task, as stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskRecord {
+    pub id: TaskId,
+    pub task: String,
+    /// Position of the task in its (sub-)stack. Higher is closer to the top.
+    pub order: f64,
+    pub stack_id: StackId,
+    /// Task whose sub-stack this task is in, if any.
+    pub parent_id: Option<TaskId>,
+    pub status: TaskStatus,
+    pub created_at: Option<Timestamp>,
+    pub finished_at: Option<Timestamp>,
+}
+
+impl TaskRecord {
+    /// Get the (sub-)stack the task is in.
+    pub fn context(&self) -> Context {
+        Context { stack_id: self.stack_id, parent_id: self.parent_id }
+    }
+}
+
+/// A binding of a stack to a directory, as stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BindingRecord {
+    pub path: PathBuf,
+    pub stack_id: StackId,
+}
+
+/// A tag on a task, as stored.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TagRecord {
+    pub task_id: TaskId,
+    pub tag: String,
+}
+
+/// A note on a task, as stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NoteRecord {
+    pub id: i64,
+    pub task_id: TaskId,
+    pub note: String,
+    pub created_at: Timestamp,
+}
+
+/// A stretch of time a task spent at the top of the current stack, as stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeEntryRecord {
+    pub id: i64,
+    /// `None` once the task has been deleted, as by undo or `dropstack`.
+    pub task_id: Option<TaskId>,
+    pub started_at: Timestamp,
+    /// `None` while the task is still being timed.
+    pub stopped_at: Option<Timestamp>,
+}
+
+/// A reminder, as stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReminderRecord {
+    pub id: String,
+    pub task_id: TaskId,
+    pub due_at: Timestamp,
+    /// Cancelled reminders are kept until they're due, but don't fire.
+    pub cancelled: bool,
+}
+
+/// Storage for stacks, tasks, reminders and the current context.
+///
+/// Methods returning several records return them in a well-defined order,
+/// breaking ties by ID, so that every store behaves the same.
+pub trait Store {
+    /// Run `f`, making either all of its changes or none of them.
+    ///
+    /// Transactions inside transactions are part of the outer one.
+    fn transaction<T>(&mut self, f: impl FnOnce(&mut Self) -> AppResult<T>) -> AppResult<T>;
+
+    /// Get the tasks commands currently operate on.
+    fn context(&self) -> AppResult<Context>;
+    fn set_context(&mut self, context: Context) -> AppResult<()>;
+
+    /// List all stacks, oldest first.
+    fn stacks(&self) -> AppResult<Vec<StackRecord>>;
+    fn stack_by_name(&self, name: &str) -> AppResult<Option<StackRecord>>;
+    /// Create an empty stack called `name`, returning its ID. The name must be unused.
+    fn insert_stack(&mut self, name: &str) -> AppResult<StackId>;
+    /// Rename the stack with the ID of `stack`. The new name must be unused.
+    fn update_stack(&mut self, stack: &StackRecord) -> AppResult<()>;
+    /// Delete a stack with all its tasks, their reminders and its bindings.
+    fn delete_stack(&mut self, stack_id: StackId) -> AppResult<()>;
+    /// Delete every task on a stack, open or not, with their reminders, tags and
+    /// notes. Their time entries are kept, without a task.
+    fn delete_tasks(&mut self, stack_id: StackId) -> AppResult<()>;
+
+    fn task(&self, task_id: TaskId) -> AppResult<Option<TaskRecord>>;
+    /// List the open tasks directly in `context`, bottom first.
+    fn open_tasks(&self, context: Context) -> AppResult<Vec<TaskRecord>>;
+    /// List the open tasks on a stack, sub-stacks included, by `order`.
+    fn open_tasks_on_stack(&self, stack_id: StackId) -> AppResult<Vec<TaskRecord>>;
+    /// List the tasks in the sub-stack of `parent_id`, whatever their status, by `order`.
+    fn subtasks(&self, parent_id: TaskId) -> AppResult<Vec<TaskRecord>>;
+    /// List tasks on `stack_id`, or every stack if it's `None`, finished at or
+    /// after `since` and before `until`, most recently finished first.
+    fn finished_tasks(&self, stack_id: Option<StackId>, since: Option<Timestamp>, until: Option<Timestamp>) -> AppResult<Vec<TaskRecord>>;
+    /// Get the lowest and highest `order` of the open tasks directly in `context`.
+    fn task_order_range(&self, context: Context) -> AppResult<Option<(f64, f64)>>;
+    /// Add a task, returning its ID. The record's own `id` is ignored.
+    fn insert_task(&mut self, task: &TaskRecord) -> AppResult<TaskId>;
+    /// Overwrite the task with the ID of `task`.
+    fn update_task(&mut self, task: &TaskRecord) -> AppResult<()>;
+
+    /// List all reminders, cancelled or not, soonest first.
+    fn reminders(&self) -> AppResult<Vec<ReminderRecord>>;
+    fn reminder(&self, reminder_id: &str) -> AppResult<Option<ReminderRecord>>;
+    fn insert_reminder(&mut self, reminder: &ReminderRecord) -> AppResult<()>;
+    /// Overwrite the reminder with the ID of `reminder`.
+    fn update_reminder(&mut self, reminder: &ReminderRecord) -> AppResult<()>;
+    fn delete_reminder(&mut self, reminder_id: &str) -> AppResult<()>;
+
+    /// List all tags, by task and then tag.
+    fn tags(&self) -> AppResult<Vec<TagRecord>>;
+    /// Tag a task. Tags it already has are ignored.
+    fn insert_tag(&mut self, tag: &TagRecord) -> AppResult<()>;
+    /// Untag a task. Tags it doesn't have are ignored.
+    fn delete_tag(&mut self, tag: &TagRecord) -> AppResult<()>;
+
+    /// List the notes on a task, oldest first.
+    fn notes(&self, task_id: TaskId) -> AppResult<Vec<NoteRecord>>;
+    /// Add a note, returning its ID. The record's own `id` is ignored.
+    fn insert_note(&mut self, note: &NoteRecord) -> AppResult<i64>;
+
+    /// List all time entries, oldest first.
+    fn time_entries(&self) -> AppResult<Vec<TimeEntryRecord>>;
+    /// Get the time entry that hasn't stopped yet, if any.
+    fn running_time_entry(&self) -> AppResult<Option<TimeEntryRecord>>;
+    /// Add a time entry, returning its ID. The record's own `id` is ignored.
+    fn insert_time_entry(&mut self, entry: &TimeEntryRecord) -> AppResult<i64>;
+    /// Overwrite the time entry with the ID of `entry`.
+    fn update_time_entry(&mut self, entry: &TimeEntryRecord) -> AppResult<()>;
+    /// Get when time tracking was paused, or `None` if it isn't.
+    fn paused_at(&self) -> AppResult<Option<Timestamp>>;
+    fn set_paused_at(&mut self, paused_at: Option<Timestamp>) -> AppResult<()>;
+
+    /// List all bindings, by path.
+    fn bindings(&self) -> AppResult<Vec<BindingRecord>>;
+    /// Bind `path` to a stack, replacing any stack bound to it already.
+    fn set_binding(&mut self, binding: &BindingRecord) -> AppResult<()>;
+    fn delete_binding(&mut self, path: &Path) -> AppResult<()>;
+}
@@ -0,0 +1,246 @@
+use super::*;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+/// A store keeping everything in memory, starting out with just the default stack.
+#[derive(Debug, Clone)]
+pub struct MemoryStore {
+    context: Context,
+    stacks: BTreeMap<StackId, StackRecord>,
+    tasks: BTreeMap<TaskId, TaskRecord>,
+    reminders: BTreeMap<String, ReminderRecord>,
+    bindings: BTreeMap<PathBuf, BindingRecord>,
+    tags: BTreeSet<TagRecord>,
+    notes: BTreeMap<i64, NoteRecord>,
+    time_entries: BTreeMap<i64, TimeEntryRecord>,
+    paused_at: Option<Timestamp>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        let default_stack = StackRecord { id: DEFAULT_STACK_ID, name: "default".into() };
+        MemoryStore {
+            context: Context { stack_id: DEFAULT_STACK_ID, parent_id: None },
+            stacks: BTreeMap::from([(DEFAULT_STACK_ID, default_stack)]),
+            tasks: BTreeMap::new(),
+            reminders: BTreeMap::new(),
+            bindings: BTreeMap::new(),
+            tags: BTreeSet::new(),
+            notes: BTreeMap::new(),
+            time_entries: BTreeMap::new(),
+            paused_at: None,
+        }
+    }
+
+    /// Get the tasks matching `filter`, sorted by `order` and then ID.
+    fn tasks_by_order(&self, filter: impl Fn(&TaskRecord) -> bool) -> Vec<TaskRecord> {
+        let mut tasks: Vec<TaskRecord> = self.tasks.values().filter(|task| filter(task)).cloned().collect();
+        tasks.sort_by(|a, b| a.order.total_cmp(&b.order).then(a.id.cmp(&b.id)));
+        tasks
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> MemoryStore {
+        MemoryStore::new()
+    }
+}
+
+impl Store for MemoryStore {
+    fn transaction<T>(&mut self, f: impl FnOnce(&mut Self) -> AppResult<T>) -> AppResult<T> {
+        let before = self.clone();
+        let result = f(self);
+        if result.is_err() {
+            *self = before;
+        }
+        result
+    }
+
+    fn context(&self) -> AppResult<Context> {
+        Ok(self.context)
+    }
+
+    fn set_context(&mut self, context: Context) -> AppResult<()> {
+        self.context = context;
+        Ok(())
+    }
+
+    fn stacks(&self) -> AppResult<Vec<StackRecord>> {
+        Ok(self.stacks.values().cloned().collect())
+    }
+
+    fn stack_by_name(&self, name: &str) -> AppResult<Option<StackRecord>> {
+        Ok(self.stacks.values().find(|stack| stack.name == name).cloned())
+    }
+
+    fn insert_stack(&mut self, name: &str) -> AppResult<StackId> {
+        let id = self.stacks.keys().last().map_or(1, |id| id + 1);
+        self.stacks.insert(id, StackRecord { id, name: name.into() });
+        Ok(id)
+    }
+
+    fn update_stack(&mut self, stack: &StackRecord) -> AppResult<()> {
+        self.stacks.insert(stack.id, stack.clone());
+        Ok(())
+    }
+
+    fn delete_stack(&mut self, stack_id: StackId) -> AppResult<()> {
+        self.delete_tasks(stack_id)?;
+        self.bindings.retain(|_, binding| binding.stack_id != stack_id);
+        self.stacks.remove(&stack_id);
+        Ok(())
+    }
+
+    fn delete_tasks(&mut self, stack_id: StackId) -> AppResult<()> {
+        self.tasks.retain(|_, task| task.stack_id != stack_id);
+        let tasks = &self.tasks;
+        self.reminders.retain(|_, reminder| tasks.contains_key(&reminder.task_id));
+        self.tags.retain(|tag| tasks.contains_key(&tag.task_id));
+        self.notes.retain(|_, note| tasks.contains_key(&note.task_id));
+        for entry in self.time_entries.values_mut() {
+            entry.task_id = entry.task_id.filter(|task_id| tasks.contains_key(task_id));
+        }
+        Ok(())
+    }
+
+    fn task(&self, task_id: TaskId) -> AppResult<Option<TaskRecord>> {
+        Ok(self.tasks.get(&task_id).cloned())
+    }
+
+    fn open_tasks(&self, context: Context) -> AppResult<Vec<TaskRecord>> {
+        Ok(self.tasks_by_order(|task| task.status == TaskStatus::Open && task.context() == context))
+    }
+
+    fn open_tasks_on_stack(&self, stack_id: StackId) -> AppResult<Vec<TaskRecord>> {
+        Ok(self.tasks_by_order(|task| task.status == TaskStatus::Open && task.stack_id == stack_id))
+    }
+
+    fn subtasks(&self, parent_id: TaskId) -> AppResult<Vec<TaskRecord>> {
+        Ok(self.tasks_by_order(|task| task.parent_id == Some(parent_id)))
+    }
+
+    fn finished_tasks(&self, stack_id: Option<StackId>, since: Option<Timestamp>, until: Option<Timestamp>) -> AppResult<Vec<TaskRecord>> {
+        let mut tasks: Vec<TaskRecord> = self.tasks.values()
+            .filter(|task| task.status != TaskStatus::Open)
+            .filter(|task| stack_id.is_none_or(|id| task.stack_id == id))
+            .filter(|task| since.is_none_or(|since| task.finished_at.is_some_and(|t| t >= since)))
+            .filter(|task| until.is_none_or(|until| task.finished_at.is_some_and(|t| t < until)))
+            .cloned()
+            .collect();
+        tasks.sort_by(|a, b| b.finished_at.cmp(&a.finished_at).then(b.id.cmp(&a.id)));
+        Ok(tasks)
+    }
+
+    fn task_order_range(&self, context: Context) -> AppResult<Option<(f64, f64)>> {
+        Ok(self.tasks.values()
+            .filter(|task| task.status == TaskStatus::Open && task.context() == context)
+            .map(|task| (task.order, task.order))
+            .reduce(|(min, max), (order, _)| (min.min(order), max.max(order))))
+    }
+
+    fn insert_task(&mut self, task: &TaskRecord) -> AppResult<TaskId> {
+        let id = self.tasks.keys().last().map_or(1, |id| id + 1);
+        self.tasks.insert(id, TaskRecord { id, ..task.clone() });
+        Ok(id)
+    }
+
+    fn update_task(&mut self, task: &TaskRecord) -> AppResult<()> {
+        self.tasks.insert(task.id, task.clone());
+        Ok(())
+    }
+
+    fn reminders(&self) -> AppResult<Vec<ReminderRecord>> {
+        let mut reminders: Vec<ReminderRecord> = self.reminders.values().cloned().collect();
+        reminders.sort_by_key(|reminder| reminder.due_at);
+        Ok(reminders)
+    }
+
+    fn reminder(&self, reminder_id: &str) -> AppResult<Option<ReminderRecord>> {
+        Ok(self.reminders.get(reminder_id).cloned())
+    }
+
+    fn insert_reminder(&mut self, reminder: &ReminderRecord) -> AppResult<()> {
+        self.reminders.insert(reminder.id.clone(), reminder.clone());
+        Ok(())
+    }
+
+    fn update_reminder(&mut self, reminder: &ReminderRecord) -> AppResult<()> {
+        self.insert_reminder(reminder)
+    }
+
+    fn delete_reminder(&mut self, reminder_id: &str) -> AppResult<()> {
+        self.reminders.remove(reminder_id);
+        Ok(())
+    }
+
+    fn tags(&self) -> AppResult<Vec<TagRecord>> {
+        Ok(self.tags.iter().cloned().collect())
+    }
+
+    fn insert_tag(&mut self, tag: &TagRecord) -> AppResult<()> {
+        self.tags.insert(tag.clone());
+        Ok(())
+    }
+
+    fn delete_tag(&mut self, tag: &TagRecord) -> AppResult<()> {
+        self.tags.remove(tag);
+        Ok(())
+    }
+
+    fn notes(&self, task_id: TaskId) -> AppResult<Vec<NoteRecord>> {
+        let mut notes: Vec<NoteRecord> = self.notes.values().filter(|note| note.task_id == task_id).cloned().collect();
+        notes.sort_by_key(|note| note.created_at);
+        Ok(notes)
+    }
+
+    fn insert_note(&mut self, note: &NoteRecord) -> AppResult<i64> {
+        let id = self.notes.keys().last().map_or(1, |id| id + 1);
+        self.notes.insert(id, NoteRecord { id, ..note.clone() });
+        Ok(id)
+    }
+
+    fn time_entries(&self) -> AppResult<Vec<TimeEntryRecord>> {
+        let mut entries: Vec<TimeEntryRecord> = self.time_entries.values().cloned().collect();
+        entries.sort_by_key(|entry| entry.started_at);
+        Ok(entries)
+    }
+
+    fn running_time_entry(&self) -> AppResult<Option<TimeEntryRecord>> {
+        Ok(self.time_entries.values().find(|entry| entry.stopped_at.is_none()).cloned())
+    }
+
+    fn insert_time_entry(&mut self, entry: &TimeEntryRecord) -> AppResult<i64> {
+        let id = self.time_entries.keys().last().map_or(1, |id| id + 1);
+        self.time_entries.insert(id, TimeEntryRecord { id, ..entry.clone() });
+        Ok(id)
+    }
+
+    fn update_time_entry(&mut self, entry: &TimeEntryRecord) -> AppResult<()> {
+        self.time_entries.insert(entry.id, entry.clone());
+        Ok(())
+    }
+
+    fn paused_at(&self) -> AppResult<Option<Timestamp>> {
+        Ok(self.paused_at)
+    }
+
+    fn set_paused_at(&mut self, paused_at: Option<Timestamp>) -> AppResult<()> {
+        self.paused_at = paused_at;
+        Ok(())
+    }
+
+    fn bindings(&self) -> AppResult<Vec<BindingRecord>> {
+        Ok(self.bindings.values().cloned().collect())
+    }
+
+    fn set_binding(&mut self, binding: &BindingRecord) -> AppResult<()> {
+        self.bindings.insert(binding.path.clone(), binding.clone());
+        Ok(())
+    }
+
+    fn delete_binding(&mut self, path: &Path) -> AppResult<()> {
+        self.bindings.remove(path);
+        Ok(())
+    }
+}
@@ -0,0 +1,320 @@
+use super::*;
+
+use std::path::Path;
+use std::time::Duration;
+
+use rusqlite::{Connection, Row, params, named_params, OptionalExtension};
+use rusqlite::Result as RusqliteResult;
+
+/// Columns to select to read a task with [`task_from_row`].
+const TASK_COLUMNS: &str = "id, task, task_order, stack_id, parent_id, status, created_at, finished_at";
+
+/// Read a task selected with [`TASK_COLUMNS`].
+fn task_from_row(row: &Row) -> RusqliteResult<TaskRecord> {
+    let status: String = row.get(5)?;
+    Ok(TaskRecord {
+        id: row.get(0)?,
+        task: row.get(1)?,
+        order: row.get(2)?,
+        stack_id: row.get(3)?,
+        parent_id: row.get(4)?,
+        status: TaskStatus::from_db(&status).expect("bug: invalid task status in database"),
+        created_at: row.get(6)?,
+        finished_at: row.get(7)?,
+    })
+}
+
+/// Columns to select to read a time entry with [`time_entry_from_row`].
+const TIME_ENTRY_COLUMNS: &str = "id, task_id, started_at, stopped_at";
+
+/// Read a time entry selected with [`TIME_ENTRY_COLUMNS`].
+fn time_entry_from_row(row: &Row) -> RusqliteResult<TimeEntryRecord> {
+    Ok(TimeEntryRecord {
+        id: row.get(0)?,
+        task_id: row.get(1)?,
+        started_at: row.get(2)?,
+        stopped_at: row.get(3)?,
+    })
+}
+
+/// Columns to select to read a reminder with [`reminder_from_row`].
+const REMINDER_COLUMNS: &str = "id, task_id, due_at, status = 'cancelled'";
+
+/// Read a reminder selected with [`REMINDER_COLUMNS`].
+fn reminder_from_row(row: &Row) -> RusqliteResult<ReminderRecord> {
+    Ok(ReminderRecord {
+        id: row.get(0)?,
+        task_id: row.get(1)?,
+        due_at: row.get(2)?,
+        cancelled: row.get(3)?,
+    })
+}
+
+/// A store in a SQLite database, laid out as in [`crate::migrations`].
+pub struct SqliteStore {
+    db: Connection,
+}
+
+impl SqliteStore {
+    /// Open or create the database at `path`. The database isn't migrated.
+    pub fn open(path: &Path) -> AppResult<SqliteStore> {
+        let db = Connection::open(path)
+            .map_err(|e| AppError::Environment(format!("unable to open yakstack database: {}", e)))?;
+        // DB could be locked by a previous remind command.
+        db.busy_timeout(Duration::from_secs(1))?;
+        SqliteStore::from_connection(db)
+    }
+
+    /// Create a database in memory. The database isn't migrated.
+    pub fn open_in_memory() -> AppResult<SqliteStore> {
+        SqliteStore::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(db: Connection) -> AppResult<SqliteStore> {
+        db.execute("PRAGMA foreign_keys = ON", [])?;
+        Ok(SqliteStore { db })
+    }
+
+    /// Get the underlying connection.
+    pub fn connection(&self) -> &Connection {
+        &self.db
+    }
+
+    pub fn connection_mut(&mut self) -> &mut Connection {
+        &mut self.db
+    }
+
+    /// Close the database, reporting any error doing so.
+    pub fn close(self) -> AppResult<()> {
+        self.db.close().map_err(|(_, e)| e.into())
+    }
+
+    /// Run `query` with `params`, collecting the tasks it selects.
+    fn query_tasks(&self, query: &str, params: impl rusqlite::Params) -> AppResult<Vec<TaskRecord>> {
+        let mut stmt = self.db.prepare(query)?;
+        let tasks = stmt.query_map(params, task_from_row)?.collect::<RusqliteResult<Vec<TaskRecord>>>()?;
+        Ok(tasks)
+    }
+}
+
+impl Store for SqliteStore {
+    fn transaction<T>(&mut self, f: impl FnOnce(&mut Self) -> AppResult<T>) -> AppResult<T> {
+        if !self.db.is_autocommit() {
+            return f(self);
+        }
+        // Take the write lock up front, so that reads in `f` can't be invalidated.
+        self.db.execute_batch("BEGIN IMMEDIATE")?;
+        let result = f(self);
+        self.db.execute_batch(if result.is_ok() { "COMMIT" } else { "ROLLBACK" })?;
+        result
+    }
+
+    fn context(&self) -> AppResult<Context> {
+        let context = self.db.query_row("SELECT stack_id, parent_id FROM app_state", [], |row| Ok(Context {
+            stack_id: row.get(0)?,
+            parent_id: row.get(1)?,
+        }))?;
+        Ok(context)
+    }
+
+    fn set_context(&mut self, context: Context) -> AppResult<()> {
+        self.db.execute("UPDATE app_state SET stack_id = ?, parent_id = ?", params![context.stack_id, context.parent_id])?;
+        Ok(())
+    }
+
+    fn stacks(&self) -> AppResult<Vec<StackRecord>> {
+        let mut stmt = self.db.prepare("SELECT id, name FROM stacks ORDER BY id")?;
+        let stacks = stmt.query_map([], |row| Ok(StackRecord { id: row.get(0)?, name: row.get(1)? }))?
+            .collect::<RusqliteResult<Vec<StackRecord>>>()?;
+        Ok(stacks)
+    }
+
+    fn stack_by_name(&self, name: &str) -> AppResult<Option<StackRecord>> {
+        let stack = self.db.query_row("SELECT id, name FROM stacks WHERE name = ?", params![name],
+            |row| Ok(StackRecord { id: row.get(0)?, name: row.get(1)? })).optional()?;
+        Ok(stack)
+    }
+
+    fn insert_stack(&mut self, name: &str) -> AppResult<StackId> {
+        self.db.execute("INSERT INTO stacks(name) VALUES (?)", params![name])?;
+        Ok(self.db.last_insert_rowid() as StackId)
+    }
+
+    fn update_stack(&mut self, stack: &StackRecord) -> AppResult<()> {
+        self.db.execute("UPDATE stacks SET name = ? WHERE id = ?", params![stack.name, stack.id])?;
+        Ok(())
+    }
+
+    fn delete_stack(&mut self, stack_id: StackId) -> AppResult<()> {
+        // Bindings go with the stack, through ON DELETE CASCADE.
+        self.delete_tasks(stack_id)?;
+        self.db.execute("DELETE FROM stacks WHERE id = ?", params![stack_id])?;
+        Ok(())
+    }
+
+    fn delete_tasks(&mut self, stack_id: StackId) -> AppResult<()> {
+        // Reminders, tags and notes go with their tasks, through ON DELETE CASCADE,
+        // and time entries lose theirs, through ON DELETE SET NULL.
+        self.db.execute("DELETE FROM tasks WHERE stack_id = ?", params![stack_id])?;
+        Ok(())
+    }
+
+    fn task(&self, task_id: TaskId) -> AppResult<Option<TaskRecord>> {
+        let task = self.db.query_row(&format!("SELECT {} FROM tasks WHERE id = ?", TASK_COLUMNS), params![task_id], task_from_row).optional()?;
+        Ok(task)
+    }
+
+    fn open_tasks(&self, context: Context) -> AppResult<Vec<TaskRecord>> {
+        self.query_tasks(&format!("SELECT {} FROM tasks WHERE stack_id = ? AND parent_id IS ? AND status = 'open' ORDER BY task_order, id", TASK_COLUMNS),
+            params![context.stack_id, context.parent_id])
+    }
+
+    fn open_tasks_on_stack(&self, stack_id: StackId) -> AppResult<Vec<TaskRecord>> {
+        self.query_tasks(&format!("SELECT {} FROM tasks WHERE stack_id = ? AND status = 'open' ORDER BY task_order, id", TASK_COLUMNS),
+            params![stack_id])
+    }
+
+    fn subtasks(&self, parent_id: TaskId) -> AppResult<Vec<TaskRecord>> {
+        self.query_tasks(&format!("SELECT {} FROM tasks WHERE parent_id = ? ORDER BY task_order, id", TASK_COLUMNS), params![parent_id])
+    }
+
+    fn finished_tasks(&self, stack_id: Option<StackId>, since: Option<Timestamp>, until: Option<Timestamp>) -> AppResult<Vec<TaskRecord>> {
+        self.query_tasks(&format!("SELECT {} FROM tasks
+            WHERE status != 'open'
+            AND (:stack_id IS NULL OR stack_id = :stack_id)
+            AND (:since IS NULL OR finished_at >= :since)
+            AND (:until IS NULL OR finished_at < :until)
+            ORDER BY finished_at DESC, id DESC", TASK_COLUMNS),
+            named_params! {":stack_id": stack_id, ":since": since, ":until": until})
+    }
+
+    fn task_order_range(&self, context: Context) -> AppResult<Option<(f64, f64)>> {
+        let range: (Option<f64>, Option<f64>) = self.db.query_row(
+            "SELECT min(task_order), max(task_order) FROM tasks WHERE stack_id = ? AND parent_id IS ? AND status = 'open'",
+            params![context.stack_id, context.parent_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(range.0.zip(range.1))
+    }
+
+    fn insert_task(&mut self, task: &TaskRecord) -> AppResult<TaskId> {
+        self.db.execute("INSERT INTO tasks(task, task_order, stack_id, parent_id, status, created_at, finished_at) VALUES (?, ?, ?, ?, ?, ?, ?)",
+            params![task.task, task.order, task.stack_id, task.parent_id, task.status.as_str(), task.created_at, task.finished_at])?;
+        Ok(self.db.last_insert_rowid())
+    }
+
+    fn update_task(&mut self, task: &TaskRecord) -> AppResult<()> {
+        self.db.execute("UPDATE tasks SET task = ?, task_order = ?, stack_id = ?, parent_id = ?, status = ?, created_at = ?, finished_at = ? WHERE id = ?",
+            params![task.task, task.order, task.stack_id, task.parent_id, task.status.as_str(), task.created_at, task.finished_at, task.id])?;
+        Ok(())
+    }
+
+    fn reminders(&self) -> AppResult<Vec<ReminderRecord>> {
+        let mut stmt = self.db.prepare(&format!("SELECT {} FROM reminders ORDER BY due_at, id", REMINDER_COLUMNS))?;
+        let reminders = stmt.query_map([], reminder_from_row)?.collect::<RusqliteResult<Vec<ReminderRecord>>>()?;
+        Ok(reminders)
+    }
+
+    fn reminder(&self, reminder_id: &str) -> AppResult<Option<ReminderRecord>> {
+        let reminder = self.db.query_row(&format!("SELECT {} FROM reminders WHERE id = ?", REMINDER_COLUMNS), params![reminder_id], reminder_from_row).optional()?;
+        Ok(reminder)
+    }
+
+    fn insert_reminder(&mut self, reminder: &ReminderRecord) -> AppResult<()> {
+        // `delay` predates `due_at`, and is only kept for older versions' sake.
+        self.db.execute("INSERT INTO reminders(id, delay, due_at, task_id, status) VALUES (?, max(? - unixepoch(), 1), ?, ?, ?)",
+            params![reminder.id, reminder.due_at, reminder.due_at, reminder.task_id, reminder_status(reminder)])?;
+        Ok(())
+    }
+
+    fn update_reminder(&mut self, reminder: &ReminderRecord) -> AppResult<()> {
+        self.db.execute("UPDATE reminders SET task_id = ?, due_at = ?, status = ? WHERE id = ?",
+            params![reminder.task_id, reminder.due_at, reminder_status(reminder), reminder.id])?;
+        Ok(())
+    }
+
+    fn delete_reminder(&mut self, reminder_id: &str) -> AppResult<()> {
+        self.db.execute("DELETE FROM reminders WHERE id = ?", params![reminder_id])?;
+        Ok(())
+    }
+
+    fn tags(&self) -> AppResult<Vec<TagRecord>> {
+        let mut stmt = self.db.prepare("SELECT task_id, tag FROM tags ORDER BY task_id, tag")?;
+        let tags = stmt.query_map([], |row| Ok(TagRecord { task_id: row.get(0)?, tag: row.get(1)? }))?
+            .collect::<RusqliteResult<Vec<TagRecord>>>()?;
+        Ok(tags)
+    }
+
+    fn insert_tag(&mut self, tag: &TagRecord) -> AppResult<()> {
+        self.db.execute("INSERT OR IGNORE INTO tags(task_id, tag) VALUES (?, ?)", params![tag.task_id, tag.tag])?;
+        Ok(())
+    }
+
+    fn delete_tag(&mut self, tag: &TagRecord) -> AppResult<()> {
+        self.db.execute("DELETE FROM tags WHERE task_id = ? AND tag = ?", params![tag.task_id, tag.tag])?;
+        Ok(())
+    }
+
+    fn notes(&self, task_id: TaskId) -> AppResult<Vec<NoteRecord>> {
+        let mut stmt = self.db.prepare("SELECT id, task_id, note, created_at FROM notes WHERE task_id = ? ORDER BY created_at, id")?;
+        let notes = stmt.query_map(params![task_id], |row| Ok(NoteRecord { id: row.get(0)?, task_id: row.get(1)?, note: row.get(2)?, created_at: row.get(3)? }))?
+            .collect::<RusqliteResult<Vec<NoteRecord>>>()?;
+        Ok(notes)
+    }
+
+    fn insert_note(&mut self, note: &NoteRecord) -> AppResult<i64> {
+        self.db.execute("INSERT INTO notes(task_id, note, created_at) VALUES (?, ?, ?)", params![note.task_id, note.note, note.created_at])?;
+        Ok(self.db.last_insert_rowid())
+    }
+
+    fn time_entries(&self) -> AppResult<Vec<TimeEntryRecord>> {
+        let mut stmt = self.db.prepare(&format!("SELECT {} FROM time_entries ORDER BY started_at, id", TIME_ENTRY_COLUMNS))?;
+        let entries = stmt.query_map([], time_entry_from_row)?.collect::<RusqliteResult<Vec<TimeEntryRecord>>>()?;
+        Ok(entries)
+    }
+
+    fn running_time_entry(&self) -> AppResult<Option<TimeEntryRecord>> {
+        let entry = self.db.query_row(&format!("SELECT {} FROM time_entries WHERE stopped_at IS NULL", TIME_ENTRY_COLUMNS), [], time_entry_from_row).optional()?;
+        Ok(entry)
+    }
+
+    fn insert_time_entry(&mut self, entry: &TimeEntryRecord) -> AppResult<i64> {
+        self.db.execute("INSERT INTO time_entries(task_id, started_at, stopped_at) VALUES (?, ?, ?)", params![entry.task_id, entry.started_at, entry.stopped_at])?;
+        Ok(self.db.last_insert_rowid())
+    }
+
+    fn update_time_entry(&mut self, entry: &TimeEntryRecord) -> AppResult<()> {
+        self.db.execute("UPDATE time_entries SET task_id = ?, started_at = ?, stopped_at = ? WHERE id = ?", params![entry.task_id, entry.started_at, entry.stopped_at, entry.id])?;
+        Ok(())
+    }
+
+    fn paused_at(&self) -> AppResult<Option<Timestamp>> {
+        Ok(self.db.query_row("SELECT paused_at FROM timer", [], |row| row.get(0))?)
+    }
+
+    fn set_paused_at(&mut self, paused_at: Option<Timestamp>) -> AppResult<()> {
+        self.db.execute("UPDATE timer SET paused_at = ?", params![paused_at])?;
+        Ok(())
+    }
+
+    fn bindings(&self) -> AppResult<Vec<BindingRecord>> {
+        let mut stmt = self.db.prepare("SELECT path, stack_id FROM bindings ORDER BY path")?;
+        let bindings = stmt.query_map([], |row| Ok(BindingRecord { path: row.get::<_, String>(0)?.into(), stack_id: row.get(1)? }))?
+            .collect::<RusqliteResult<Vec<BindingRecord>>>()?;
+        Ok(bindings)
+    }
+
+    fn set_binding(&mut self, binding: &BindingRecord) -> AppResult<()> {
+        self.db.execute("INSERT INTO bindings(path, stack_id) VALUES (?, ?) ON CONFLICT(path) DO UPDATE SET stack_id = excluded.stack_id",
+            params![binding.path.to_string_lossy(), binding.stack_id])?;
+        Ok(())
+    }
+
+    fn delete_binding(&mut self, path: &Path) -> AppResult<()> {
+        self.db.execute("DELETE FROM bindings WHERE path = ?", params![path.to_string_lossy()])?;
+        Ok(())
+    }
+}
+
+/// Get the value stored in the `reminders.status` column for `reminder`.
+fn reminder_status(reminder: &ReminderRecord) -> &'static str {
+    if reminder.cancelled { "cancelled" } else { "pending" }
+}
@@ -0,0 +1,238 @@
+//! Parsing of reminder times.
+//!
+//! A time spec is either a duration from now, or a point in local time:
+//!
+//! * Durations are one or more amounts with units, optionally separated by
+//!   spaces: `90s`, `1h30m`, `2d`, `1w 2d`. Units are `s`, `m`, `h`, `d` and
+//!   `w`.
+//! * Points in time are a day, a clock time, or both, in either order:
+//!   `16:00`, `9am`, `9:30pm`, `tomorrow`, `monday 9am`, `2026-12-24 18:00`.
+//!   Days are `today`, `tomorrow`, a weekday (`monday` or `mon`), meaning the
+//!   next one after today, or a `YYYY-MM-DD` date. A clock time on its own
+//!   means the next time the clock shows it; a day on its own means
+//!   [`DEFAULT_TIME`] on that day.
+//!
+//! Specs are case-insensitive, and anything that doesn't parse completely is
+//! rejected rather than partially understood.
+
+use crate::errors::ReminderError;
+
+use std::sync::LazyLock;
+
+use chrono::{DateTime, Datelike, Days, Duration, Local, NaiveDate, NaiveTime, TimeZone, Weekday};
+use regex::Regex;
+
+/// Time of day used when a spec only names a day: 9am.
+pub const DEFAULT_TIME: NaiveTime = match NaiveTime::from_hms_opt(9, 0, 0) {
+    Some(time) => time,
+    None => panic!("bug: invalid default time"),
+};
+
+/// Get the time `spec` refers to, relative to `now`.
+///
+/// The result is always after `now`.
+pub fn parse<Tz: TimeZone>(spec: &str, now: &DateTime<Tz>) -> Result<DateTime<Tz>, ReminderError> {
+    let invalid = || ReminderError::InvalidReminderTime(spec.into());
+    let spec_lower = spec.to_lowercase();
+    let words: Vec<&str> = spec_lower.split_whitespace().collect();
+    if words.is_empty() {
+        return Err(invalid());
+    }
+
+    let time = if let Ok(seconds) = parse_duration(&spec_lower) {
+        now.clone() + Duration::seconds(seconds)
+    } else {
+        let mut day = None;
+        let mut time_of_day = None;
+        for word in words {
+            if let (None, Some(d)) = (day, parse_day(word, now.date_naive())) {
+                day = Some(d);
+            } else if let (None, Some(t)) = (time_of_day, parse_time_of_day(word)) {
+                time_of_day = Some(t);
+            } else {
+                return Err(invalid());
+            }
+        }
+        let date = match (day, time_of_day) {
+            (Some(date), _) => date,
+            // A clock time that has passed today means tomorrow.
+            (None, Some(t)) if t <= now.time() => now.date_naive() + Days::new(1),
+            (None, _) => now.date_naive(),
+        };
+        let local = date.and_time(time_of_day.unwrap_or(DEFAULT_TIME));
+        // Times skipped by a DST change don't exist; times repeated by one are
+        // taken the first time around.
+        now.timezone().from_local_datetime(&local).earliest().ok_or_else(invalid)?
+    };
+
+    if time <= *now {
+        return Err(ReminderError::TimeInPast(spec.into()));
+    }
+    Ok(time)
+}
+
+/// Get the number of seconds in the duration `spec`, like `1h30m`.
+///
+/// Durations must be longer than zero.
+pub fn parse_duration(spec: &str) -> Result<i64, ReminderError> {
+    let invalid = || ReminderError::InvalidReminderTime(spec.into());
+    static COMPONENT: LazyLock<Regex> = LazyLock::new(|| Regex::new("([0-9]+)([smhdw])").expect("bug: invalid duration regex"));
+    static WHOLE: LazyLock<Regex> = LazyLock::new(|| Regex::new("^([0-9]+[smhdw])+$").expect("bug: invalid duration regex"));
+    let compact = spec.split_whitespace().collect::<String>().to_lowercase();
+    if !WHOLE.is_match(&compact) {
+        return Err(invalid());
+    }
+
+    let mut seconds: i64 = 0;
+    for caps in COMPONENT.captures_iter(&compact) {
+        let amount: i64 = caps[1].parse().map_err(|_| invalid())?;
+        let unit_seconds = match &caps[2] {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            "w" => 7 * 24 * 60 * 60,
+            unit => unreachable!("bug: unknown duration unit {}", unit),
+        };
+        seconds = amount.checked_mul(unit_seconds)
+            .and_then(|s| seconds.checked_add(s))
+            // Keep well clear of the limits of chrono and timestamps.
+            .filter(|s| *s <= 100 * 366 * 24 * 60 * 60)
+            .ok_or_else(invalid)?;
+    }
+    if seconds == 0 {
+        return Err(invalid());
+    }
+    Ok(seconds)
+}
+
+/// Get the first moment of `date` in local time.
+pub fn start_of_day(date: NaiveDate) -> i64 {
+    let midnight = date.and_time(NaiveTime::MIN);
+    match Local.from_local_datetime(&midnight).earliest() {
+        Some(time) => time.timestamp(),
+        // Midnight was skipped by a DST change.
+        None => midnight.and_utc().timestamp(),
+    }
+}
+
+/// Parse a day: `today`, `tomorrow`, a weekday after `today`, or a date.
+fn parse_day(word: &str, today: NaiveDate) -> Option<NaiveDate> {
+    match word {
+        "today" => Some(today),
+        "tomorrow" => Some(today + Days::new(1)),
+        _ => {
+            if let Ok(weekday) = word.parse::<Weekday>() {
+                let days_ahead = (weekday.num_days_from_monday() + 7 - today.weekday().num_days_from_monday()) % 7;
+                let days_ahead = if days_ahead == 0 { 7 } else { days_ahead };
+                return Some(today + Days::new(days_ahead.into()));
+            }
+            static DATE_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new("^[0-9]{4}-[0-9]{2}-[0-9]{2}$").expect("bug: invalid date regex"));
+            DATE_REGEX.is_match(word).then(|| NaiveDate::parse_from_str(word, "%Y-%m-%d").ok()).flatten()
+        }
+    }
+}
+
+/// Parse a clock time: `16:00`, `9am`, `9:30pm`. A bare number isn't a time.
+fn parse_time_of_day(word: &str) -> Option<NaiveTime> {
+    static TIME_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new("^([0-9]{1,2})(?::([0-9]{2}))?(am|pm)?$").expect("bug: invalid time regex"));
+    let caps = TIME_REGEX.captures(word)?;
+    let hour: u32 = caps[1].parse().ok()?;
+    let minute: u32 = caps.get(2).map_or(Some(0), |m| m.as_str().parse().ok())?;
+    let hour = match caps.get(3).map(|m| m.as_str()) {
+        None if caps.get(2).is_none() => return None,
+        None => hour,
+        Some(_) if !(1..=12).contains(&hour) => return None,
+        Some("am") => hour % 12,
+        Some(_) => hour % 12 + 12,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, Utc};
+
+    /// Wednesday 2026-10-14, 10:30:00 UTC.
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 10, 14, 10, 30, 0).unwrap()
+    }
+
+    fn at(spec: &str) -> DateTime<Utc> {
+        parse(spec, &now()).unwrap()
+    }
+
+    fn utc(month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, month, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90s").unwrap(), 90);
+        assert_eq!(parse_duration("1h30m").unwrap(), 90 * 60);
+        assert_eq!(parse_duration("1h 30m").unwrap(), 90 * 60);
+        assert_eq!(parse_duration("2d").unwrap(), 2 * 86400);
+        assert_eq!(parse_duration("1w2d").unwrap(), 9 * 86400);
+        assert_eq!(parse_duration("1H").unwrap(), 3600);
+        assert_eq!(parse_duration("0h5m").unwrap(), 300);
+        assert_eq!(at("1h30m"), utc(10, 14, 12, 0));
+    }
+
+    #[test]
+    fn invalid_durations() {
+        for spec in ["", "1", "h", "1x", "1h30", "1h30x", "x1h", "-1h", "1.5h", "0s", "0h0m", "99999999999999999999s", "9999999w"] {
+            assert!(parse_duration(spec).is_err(), "{:?} parsed", spec);
+        }
+    }
+
+    #[test]
+    fn clock_times() {
+        assert_eq!(at("16:00"), utc(10, 14, 16, 0));
+        assert_eq!(at("4pm"), utc(10, 14, 16, 0));
+        assert_eq!(at("4:15PM"), utc(10, 14, 16, 15));
+        assert_eq!(at("12pm"), utc(10, 14, 12, 0));
+        // Already past today, so tomorrow.
+        assert_eq!(at("9am"), utc(10, 15, 9, 0));
+        assert_eq!(at("10:30"), utc(10, 15, 10, 30));
+        assert_eq!(at("12am"), utc(10, 15, 0, 0));
+        assert_eq!(at("0:05"), utc(10, 15, 0, 5));
+    }
+
+    #[test]
+    fn days() {
+        assert_eq!(at("tomorrow"), utc(10, 15, 9, 0));
+        assert_eq!(at("tomorrow 9am"), utc(10, 15, 9, 0));
+        assert_eq!(at("9am tomorrow"), utc(10, 15, 9, 0));
+        assert_eq!(at("today 18:00"), utc(10, 14, 18, 0));
+        assert_eq!(at("friday"), utc(10, 16, 9, 0));
+        assert_eq!(at("Mon 14:00"), utc(10, 19, 14, 0));
+        // Today is Wednesday, so this is next week's.
+        assert_eq!(at("wednesday 11:00"), utc(10, 21, 11, 0));
+        assert_eq!(at("2026-12-24"), utc(12, 24, 9, 0));
+        assert_eq!(at("2026-12-24 18:30"), utc(12, 24, 18, 30));
+    }
+
+    #[test]
+    fn invalid_times() {
+        for spec in ["", " ", "9", "25:00", "12:60", "13pm", "0am", "9 am", "tomorrow tomorrow", "9am 10am",
+            "tomorrow 1h", "noon", "2026-13-01", "2026-02-30", "26-12-24", "at 9am", "tomorrow, 9am", "1h30m tomorrow"] {
+            assert!(matches!(parse(spec, &now()), Err(ReminderError::InvalidReminderTime(_))), "{:?} parsed", spec);
+        }
+    }
+
+    #[test]
+    fn times_in_the_past() {
+        for spec in ["today", "today 10:00", "2026-10-01", "2025-12-24 18:00"] {
+            assert!(matches!(parse(spec, &now()), Err(ReminderError::TimeInPast(_))), "{:?} accepted", spec);
+        }
+    }
+
+    #[test]
+    fn times_are_local() {
+        let tz = FixedOffset::east_opt(2 * 3600).unwrap();
+        let now = now().with_timezone(&tz);
+        assert_eq!(parse("16:00", &now).unwrap(), tz.with_ymd_and_hms(2026, 10, 14, 16, 0, 0).unwrap());
+        assert_eq!(parse("16:00", &now).unwrap().timestamp(), utc(10, 14, 14, 0).timestamp());
+    }
+}
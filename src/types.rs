@@ -1,3 +1,437 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::errors::TaskError;
+
 pub type StackId = u32;
 pub type TaskIndex = u64;
+pub type TaskId = i64;
 pub const DEFAULT_STACK_ID: StackId = 1;
+/// Seconds since the Unix epoch.
+pub type Timestamp = i64;
+
+/// A reference to a task given on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskRef {
+    /// Position in the current (sub-)stack, starting from 0 at the bottom.
+    /// Changes as tasks are added and removed.
+    Index(TaskIndex),
+    /// Task ID, written in hex after a `#`, e.g. `#a3f`. Never changes.
+    Id(TaskId),
+}
+
+impl FromStr for TaskRef {
+    type Err = TaskError;
+
+    fn from_str(s: &str) -> Result<TaskRef, TaskError> {
+        let invalid = || TaskError::InvalidTaskRef(s.into());
+        match s.strip_prefix('#') {
+            Some(hex) => TaskId::from_str_radix(hex, 16).ok()
+                .filter(|id| *id >= 0 && !hex.starts_with('+'))
+                .map(TaskRef::Id)
+                .ok_or_else(invalid),
+            None => s.parse().map(TaskRef::Index).map_err(|_| invalid()),
+        }
+    }
+}
+
+impl fmt::Display for TaskRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TaskRef::Index(index) => write!(f, "{}", index),
+            TaskRef::Id(id) => write!(f, "#{:x}", id),
+        }
+    }
+}
+
+/// Check whether `tag` is a valid tag: letters, digits, `_` and `-`.
+pub fn is_tag(tag: &str) -> bool {
+    !tag.is_empty() && tag.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Parse a tag given on the command line, dropping any leading `#`.
+pub fn parse_tag(tag: &str) -> Result<String, TaskError> {
+    let tag = tag.strip_prefix('#').unwrap_or(tag);
+    if is_tag(tag) {
+        Ok(tag.into())
+    } else {
+        Err(TaskError::InvalidTag(tag.into()))
+    }
+}
+
+/// Get the tags written in a task's text, like `#ci`.
+///
+/// Inline tags must start with a letter, so that issue numbers like `#12` aren't taken for tags.
+pub fn inline_tags(text: &str) -> Vec<String> {
+    // Unicode classes make this slow to compile, and it's used for every task.
+    static TAG_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?:^|\s)#(\p{L}[\w-]*)").expect("bug: invalid tag regex"));
+    let mut tags: Vec<String> = Vec::new();
+    for caps in TAG_REGEX.captures_iter(text) {
+        if !tags.iter().any(|tag| *tag == caps[1]) {
+            tags.push(caps[1].into());
+        }
+    }
+    tags
+}
+
+/// Which tags tasks must have, and must not have, to be listed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagFilter {
+    /// Tags a task must all have.
+    pub tags: Vec<String>,
+    /// Tags a task must have none of.
+    pub not_tags: Vec<String>,
+}
+
+impl TagFilter {
+    /// Check whether `task` passes the filter.
+    pub fn matches(&self, task: &Task) -> bool {
+        self.tags.iter().all(|tag| task.tags.contains(tag)) && !self.not_tags.iter().any(|tag| task.tags.contains(tag))
+    }
+}
+
+/// A run of neighbouring tasks given on the command line, like `2..4`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskRange {
+    /// One end of the run. The ends can be given either way round.
+    pub start: TaskRef,
+    pub end: TaskRef,
+}
+
+impl From<TaskRef> for TaskRange {
+    fn from(task: TaskRef) -> TaskRange {
+        TaskRange { start: task, end: task }
+    }
+}
+
+impl FromStr for TaskRange {
+    type Err = TaskError;
+
+    fn from_str(s: &str) -> Result<TaskRange, TaskError> {
+        match s.split_once("..") {
+            Some((start, end)) => Ok(TaskRange { start: start.parse()?, end: end.parse()? }),
+            None => s.parse::<TaskRef>().map(TaskRange::from),
+        }
+    }
+}
+
+impl fmt::Display for TaskRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}..{}", self.start, self.end)
+        }
+    }
+}
+
+/// A sed-style substitution given on the command line, like `s/old/new/g`.
+///
+/// Any punctuation can stand in for `/`; escape it with `\` where it appears in
+/// the pattern or replacement. The pattern is a [`regex`] regex. In the
+/// replacement, `&` is the whole match and `\1` to `\9` are groups. The `g`
+/// flag replaces every match instead of the first, and `i` ignores case.
+#[derive(Debug, Clone)]
+pub struct Substitution {
+    pub regex: Regex,
+    /// Replacement in the syntax of [`Regex::replace`].
+    pub replacement: String,
+    pub global: bool,
+}
+
+impl Substitution {
+    /// Apply the substitution to `text`.
+    pub fn apply(&self, text: &str) -> String {
+        let limit = if self.global { 0 } else { 1 };
+        self.regex.replacen(text, limit, self.replacement.as_str()).into_owned()
+    }
+}
+
+impl FromStr for Substitution {
+    type Err = TaskError;
+
+    fn from_str(s: &str) -> Result<Substitution, TaskError> {
+        let invalid = || TaskError::InvalidSubstitution(s.into());
+        let mut chars = s.chars();
+        if chars.next() != Some('s') {
+            return Err(invalid());
+        }
+        let delimiter = chars.next().filter(|c| c.is_ascii_punctuation() && *c != '\\').ok_or_else(invalid)?;
+        // Split at unescaped delimiters, unescaping them but leaving other escapes alone.
+        let mut parts = vec![String::new()];
+        let mut escaped = false;
+        for c in chars {
+            let part = parts.last_mut().expect("bug: no parts");
+            if escaped {
+                if c != delimiter {
+                    part.push('\\');
+                }
+                part.push(c);
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == delimiter {
+                parts.push(String::new());
+            } else {
+                part.push(c);
+            }
+        }
+        let [pattern, replacement, flags] = <[String; 3]>::try_from(parts).map_err(|_| invalid())?;
+        if escaped || !flags.chars().all(|flag| flag == 'g' || flag == 'i') {
+            return Err(invalid());
+        }
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(flags.contains('i'))
+            .build()
+            .map_err(|e| TaskError::InvalidRegex(e.to_string()))?;
+
+        // Translate sed's replacement syntax into the regex crate's.
+        let mut translated = String::new();
+        let mut replacement = replacement.chars();
+        while let Some(c) = replacement.next() {
+            match c {
+                '$' => translated.push_str("$$"),
+                '&' => translated.push_str("${0}"),
+                '\\' => match replacement.next() {
+                    Some(digit @ '1'..='9') => translated.push_str(&format!("${{{}}}", digit)),
+                    Some('n') => translated.push('\n'),
+                    Some('$') => translated.push_str("$$"),
+                    Some(other) => translated.push(other),
+                    None => return Err(invalid()),
+                },
+                _ => translated.push(c),
+            }
+        }
+        Ok(Substitution { regex, replacement: translated, global: flags.contains('g') })
+    }
+}
+
+/// Lifecycle state of a task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskStatus {
+    /// Still on its stack.
+    Open,
+    /// Popped off its stack.
+    Done,
+    /// Killed or cleared without being done.
+    Abandoned,
+}
+
+impl TaskStatus {
+    /// Get the value stored in the `tasks.status` column.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TaskStatus::Open => "open",
+            TaskStatus::Done => "done",
+            TaskStatus::Abandoned => "abandoned",
+        }
+    }
+
+    /// Parse a value of the `tasks.status` column.
+    pub fn from_db(status: &str) -> Option<TaskStatus> {
+        match status {
+            "open" => Some(TaskStatus::Open),
+            "done" => Some(TaskStatus::Done),
+            "abandoned" => Some(TaskStatus::Abandoned),
+            _ => None,
+        }
+    }
+}
+
+/// A task, as reported to the user.
+///
+/// This is also the structure of tasks in JSON output, so fields must not be
+/// renamed or removed.
+#[derive(Debug, Clone, Serialize)]
+pub struct Task {
+    /// Position of the task in its (sub-)stack, starting from 0 at the bottom.
+    /// `None` for tasks that are no longer open.
+    pub index: Option<TaskIndex>,
+    /// Identifier that doesn't change while the task exists.
+    pub id: TaskId,
+    /// `id` as accepted by commands, e.g. `#a3f`.
+    pub short_id: String,
+    pub task: String,
+    /// Name of the stack the task is on.
+    pub stack: String,
+    pub status: TaskStatus,
+    /// `None` for tasks created before yakstack recorded creation times.
+    pub created_at: Option<Timestamp>,
+    /// When the task was popped, killed or cleared.
+    pub finished_at: Option<Timestamp>,
+    /// Number of reminders for the task that haven't fired yet.
+    pub reminders: u32,
+    /// Tags, in alphabetical order, whether written in the text or not.
+    pub tags: Vec<String>,
+}
+
+/// A stack, as reported to the user.
+///
+/// This is also the structure of stacks in JSON output.
+#[derive(Debug, Clone, Serialize)]
+pub struct Stack {
+    pub id: StackId,
+    pub name: String,
+    /// Number of open tasks on the stack, including those in sub-stacks.
+    pub tasks: u64,
+    /// Whether this is the current stack.
+    pub current: bool,
+    /// Number of open tasks with each tag.
+    pub tags: BTreeMap<String, u64>,
+}
+
+/// A pending reminder.
+///
+/// This is also the structure of reminders in JSON output.
+#[derive(Debug, Clone, Serialize)]
+pub struct Reminder {
+    pub id: String,
+    pub task_id: TaskId,
+    pub task: String,
+    /// Name of the stack the task is on.
+    pub stack: String,
+    /// When the reminder fires.
+    pub due_at: Timestamp,
+}
+
+/// A note on a task.
+///
+/// This is also the structure of notes in JSON output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Note {
+    pub note: String,
+    pub created_at: Timestamp,
+}
+
+/// A task with everything attached to it, as shown by `show`.
+///
+/// This is also the structure of `show`'s JSON output.
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskDetails {
+    pub task: Task,
+    /// Oldest first.
+    pub notes: Vec<Note>,
+    /// Pending reminders, soonest first.
+    pub reminders: Vec<Reminder>,
+}
+
+/// How `time` adds up the time spent on tasks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimeGrouping {
+    /// Per task, most time first.
+    #[default]
+    Task,
+    /// Per stack, most time first.
+    Stack,
+    /// Per local day, oldest first.
+    Day,
+}
+
+/// Time spent on a task, on a stack or in a day.
+///
+/// This is also the structure of totals in JSON output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TimeTotal {
+    /// Task text, stack name or `YYYY-MM-DD` day. Time spent on tasks that
+    /// have since been deleted is added up as `(deleted tasks)`.
+    pub name: String,
+    /// ID of the task, for totals per task.
+    pub task_id: Option<TaskId>,
+    pub seconds: i64,
+}
+
+/// Where tasks merged into a stack go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MergePosition {
+    /// On top of the stack's own tasks.
+    #[default]
+    OnTop,
+    /// Underneath the stack's own tasks.
+    Below,
+}
+
+/// Where moved tasks go on their new stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovePosition {
+    /// On top of the stack.
+    Top,
+    /// At the bottom of the stack.
+    Bottom,
+    /// Right above a task, in whichever (sub-)stack it is in. Indices count
+    /// from the bottom of the destination stack.
+    After(TaskRef),
+}
+
+/// A stack bound to a directory, used whenever yakstack runs inside it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Binding {
+    pub path: PathBuf,
+    /// Name of the bound stack.
+    pub stack: String,
+}
+
+/// The tasks commands operate on: either a stack's top-level tasks, or the
+/// sub-stack of a task on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Context {
+    pub stack_id: StackId,
+    /// Task owning the sub-stack, or `None` for the stack itself.
+    pub parent_id: Option<TaskId>,
+}
+
+/// An open task, as shown by `ls --tree`.
+#[derive(Debug, Clone, Serialize)]
+pub struct TreeTask {
+    #[serde(flatten)]
+    pub task: Task,
+    /// Number of sub-stacks the task is nested in.
+    pub depth: usize,
+    /// Whether the task's sub-stack is the current context.
+    pub is_current: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_task_refs() {
+        assert_eq!("3".parse::<TaskRef>().unwrap(), TaskRef::Index(3));
+        assert_eq!("#a3f".parse::<TaskRef>().unwrap(), TaskRef::Id(0xa3f));
+        assert_eq!(TaskRef::Id(0xa3f).to_string(), "#a3f");
+        for invalid in ["", "#", "#-1", "#+1", "-1", "a3f", "#xyz"] {
+            assert!(invalid.parse::<TaskRef>().is_err(), "{:?} parsed", invalid);
+        }
+    }
+
+    #[test]
+    fn parse_tags() {
+        assert_eq!(parse_tag("#ci").unwrap(), "ci");
+        assert_eq!(parse_tag("code-review_2").unwrap(), "code-review_2");
+        for invalid in ["", "#", "two words", "a,b", "##a"] {
+            assert!(parse_tag(invalid).is_err(), "{:?} parsed", invalid);
+        }
+        assert_eq!(inline_tags("#ci: fix #flaky-test, see #12 and a#b #ci"), vec!["ci", "flaky-test"]);
+        assert!(inline_tags("no tags # here").is_empty());
+    }
+
+    #[test]
+    fn parse_substitutions() {
+        let apply = |substitution: &str, text: &str| substitution.parse::<Substitution>().unwrap().apply(text);
+        assert_eq!(apply("s/o/0/", "foo"), "f0o");
+        assert_eq!(apply("s/o/0/g", "foo"), "f00");
+        assert_eq!(apply("s/F(o+)/b\\1r/i", "Foo"), "boor");
+        assert_eq!(apply("s|/usr|[&] $HOME|", "/usr/bin"), "[/usr] $HOME/bin");
+        assert_eq!(apply("s/\\//\\&/g", "a/b"), "a&b");
+        for invalid in ["", "s", "s/a/b", "s/a/b/c/", "s/a/b/x", "sxaxbx", "s/a/b\\/", "s/(/b/"] {
+            assert!(invalid.parse::<Substitution>().is_err(), "{:?} parsed", invalid);
+        }
+    }
+}